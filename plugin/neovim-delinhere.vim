@@ -14,14 +14,12 @@ function! s:initRpc()
 endfunction
 
 " Constants for RPC messages.
-let s:DelInHere = 'DelInHere'
-let s:DelArHere = 'DelArHere'
-let s:ChaInHere = 'ChaInHere'
-let s:ChaArHere = 'ChaArHere'
-let s:SelInHere = 'SelInHere'
-let s:SelArHere = 'SelArHere'
-let s:YanInHere = 'YanInHere'
-let s:YanArHere = 'YanArHere'
+let s:Here = 'Here'
+let s:GrowHere = 'GrowHere'
+let s:ShrinkHere = 'ShrinkHere'
+let s:LookupExpr = 'LookupExpr'
+let s:TextObject = 'TextObject'
+let s:Edit = 'Edit'
 let s:Test = 'Test'
 
 " The path to the binary that was created out of 'cargo build' or 'cargo build --release". This will generally be 'target/release/name'
@@ -29,26 +27,37 @@ let s:bin = $HOME . '/.local/share/nvim/plugged/delinhere-rs/target/debug/delinh
 
 function! s:configureCommands()
     " command! -nargs=+ Add :call s:add(<f-args>)
-    command! -nargs=0 DIHTest :call s:test(<f-args>)
-    command! -nargs=0 DIHDelInHere :call s:delinhere(<f-args>)
-    command! -nargs=0 DIHDelArHere :call s:delarhere(<f-args>)
-    command! -nargs=0 DIHChaInHere :call s:chainhere(<f-args>)
-    command! -nargs=0 DIHChaArHere :call s:chaarhere(<f-args>)
-    command! -nargs=0 DIHSelInHere :call s:selinhere(<f-args>)
-    command! -nargs=0 DIHSelArHere :call s:selarhere(<f-args>)
-    command! -nargs=0 DIHYanInHere :call s:yaninhere(<f-args>)
-    command! -nargs=0 DIHYanArHere :call s:yanarhere(<f-args>)
-
-    nnoremap dih  :DIHDelInHere<CR>
-    nnoremap dah  :DIHDelArHere<CR>
-    nnoremap cih  :DIHChaInHere<CR>
-    nnoremap cah  :DIHChaArHere<CR>
-    nnoremap vih  :DIHSelInHere<CR>
-    nnoremap vah  :DIHSelArHere<CR>
-    nnoremap yih  :DIHYanInHere<CR>
-    nnoremap yah  :DIHYanArHere<CR>
-    nnoremap ;t  :DIHTest<CR>
-    nnoremap ;T  :call Testmatch()<CR>
+    command! -nargs=0 DIHTest :call s:test()
+    command! -count=1 -nargs=+ DIHHere :call s:herecommand(<count>, <f-args>)
+    command! -nargs=1 DIHGrowHere :call s:growhere(<q-args>)
+    command! -nargs=0 DIHShrinkHere :call s:shrinkhere()
+
+    " dih, dah, cih... and dinh, dilh, canh... for the next and last pairs.
+    for verb in ['d', 'c', 'v', 'y']
+      for adverb in ['i', 'a']
+        for [key, target] in [['', ''], ['n', 'next'], ['l', 'last']]
+          execute printf("nnoremap %s%s%sh :<C-u>call <SID>here({'verb': '%s', 'adverb': '%s', 'target': '%s'})<CR>",
+                \ verb, adverb, key, verb, adverb, target)
+        endfor
+      endfor
+    endfor
+    onoremap <silent> ih :<C-u>call <SID>textobject({'adverb': 'i', 'count': v:count1})<CR>
+    onoremap <silent> ah :<C-u>call <SID>textobject({'adverb': 'a', 'count': v:count1})<CR>
+    onoremap <silent> <Plug>(DelinhereRepeat) :<C-u>call <SID>repeatobject()<CR>
+    nnoremap <silent> <Plug>(DelinhereSelect) :<C-u>call <SID>repeatobject()<CR>
+    nnoremap <silent> <Plug>(DelinhereEdit) :<C-u>set operatorfunc=<SID>edit<CR>g@:<C-u><CR>
+    nnoremap <silent> <Plug>(DelinhereYank) :<C-u>call <SID>yank()<CR>
+    nnoremap <silent> <Plug>(DelinhereTest) :<C-u>set operatorfunc=<SID>rewrite<CR>g@:<C-u><CR>
+    xnoremap <silent> ih :<C-u>DIHGrowHere i<CR>
+    xnoremap <silent> ah :<C-u>DIHGrowHere a<CR>
+    xnoremap <silent> <Plug>(DelinhereShrink) :<C-u>DIHShrinkHere<CR>
+    " sh shadows Visual `s`, it is only the default.
+    if !hasmapto('<Plug>(DelinhereShrink)', 'x')
+      xmap sh <Plug>(DelinhereShrink)
+    endif
+    nnoremap ;t  :<C-u>call <SID>test()<CR>
+
+    highlight default link DelinhereHint IncSearch
 
 endfunction
 
@@ -68,46 +77,113 @@ function! s:connect()
   endif
 endfunction
 
-function! Testmatch()
-    let back=searchpairpos('{','','}','bnW', '(synIDattr(synID(line("."), col("."), 0), "name") =~? "string\\|comment")')
-    let forw=searchpairpos('{','','}','nW', '(synIDattr(synID(line("."), col("."), 0), "name") =~? "string\\|comment")')
-    echo string(back) . string(forw)
+function! s:test()
+  call rpcnotify(s:appJobId, s:Test, {'register': v:register})
 endfunction
 
-function! s:test(...)
-  call rpcnotify(s:appJobId, s:Test, a:)
+" Act on a pair. `command` is a dictionary of verb, adverb, count, register, target and pairs,
+" the count and register being the ones the mapping was typed with unless given.
+function! s:here(command)
+  let command = extend(copy(a:command), {'count': v:count1, 'register': v:register}, 'keep')
+  call rpcnotify(s:appJobId, s:Here, command)
 endfunction
 
-function! s:delinhere(...)
-  call rpcnotify(s:appJobId, s:DelInHere, a:)
+" :DIHHere verb adverb [target] [delimiter...], like :2DIHHere gU i next ( [ or :DIHHere d a ( [.
+" The target is `next` or `last`, anything else is a delimiter.
+function! s:herecommand(count, verb, adverb, ...)
+  let [target, pairs] = a:0 && index(['next', 'last'], a:1) >= 0 ? [a:1, a:000[1:]] : ['', a:000]
+  call s:here({'verb': a:verb, 'adverb': a:adverb, 'count': a:count, 'target': target, 'pairs': pairs})
 endfunction
 
-function! s:delarhere(...)
-  call rpcnotify(s:appJobId, s:DelArHere, a:)
+" The selection comes from the marks of the last one, Visual mode being over by now, along with
+" its mode and 'selection', which tell what the marks hold.
+function! s:growhere(adverb)
+  call rpcnotify(s:appJobId, s:GrowHere, win_getid(), getpos("'<")[1:2], getpos("'>")[1:2], visualmode(), &selection, a:adverb)
 endfunction
 
-function! s:chainhere(...)
-  call rpcnotify(s:appJobId, s:ChaInHere, a:)
+function! s:shrinkhere()
+  call rpcnotify(s:appJobId, s:ShrinkHere, win_getid(), getpos("'<")[1:2], getpos("'>")[1:2], visualmode(), &selection)
 endfunction
 
-function! s:chaarhere(...)
-  call rpcnotify(s:appJobId, s:ChaArHere, a:)
+" What the plugin needs to know about the buffer, for the requests it can't ask neovim about, from
+" the expression it tells. The lines only come along when it has no mirror of the buffer in sync.
+function! s:lookup()
+  return eval(rpcrequest(s:appJobId, s:LookupExpr))
 endfunction
 
-function! s:selinhere(...)
-  call rpcnotify(s:appJobId, s:SelInHere, a:)
+" Text objects select their range before the operator goes on, so they wait for it with
+" rpcrequest. `command` is like for s:here, without the verb.
+function! s:textobject(command)
+  call s:select(rpcrequest(s:appJobId, s:TextObject, s:lookup(), a:command))
 endfunction
 
-function! s:selarhere(...)
-  call rpcnotify(s:appJobId, s:SelArHere, a:)
+" Select `range`, a TextObject answer: the first and last char and the Visual mode. Nothing when
+" it is no range.
+function! s:select(range)
+  let range = a:range
+  if type(range) == v:t_list
+    call cursor(range[0])
+    execute 'normal!' range[2]
+    call cursor(range[1])
+    if range[2] ==# 'v' && &selection ==# 'exclusive'
+      " The selection has to go past the last char.
+      call cursor(range[1][0], range[1][1] + len(matchstr(getline(range[1][0]), '.', range[1][1] - 1)))
+    endif
+  endif
+endfunction
+
+" The count of a repeated command is the one of `.`, if any.
+function! s:repeated()
+  let command = copy(g:delinhere_repeat)
+  if v:count
+    let command.count = v:count
+  endif
+  return command
+endfunction
+
+" The answer to `request` for the command stored in g:delinhere_repeat. The first time the plugin
+" gives it in g:delinhere_answer, along with the buffer and changedtick it holds for, from the pair
+" it already found. It is used once, so `.` asks again for the pair at the new cursor.
+function! s:answer(request)
+  if exists('g:delinhere_answer')
+    let [buf, tick, answer] = g:delinhere_answer
+    unlet g:delinhere_answer
+    if buf == bufnr('%') && tick == b:changedtick
+      return answer
+    endif
+  endif
+  return rpcrequest(s:appJobId, a:request, s:lookup(), s:repeated())
+endfunction
+
+" The text object of the operators the plugin feeds, for the command it stored in
+" g:delinhere_repeat. Being a text object, `.` runs it again at the new cursor.
+function! s:repeatobject()
+  call s:select(s:answer(s:TextObject))
+endfunction
+
+" The operator deletes go through. Its motion is an empty command line, which leaves the cursor
+" where it is, even on an empty line, so the pair is looked up from there. The plugin sends back
+" the calls that make the edit, through the buffer API rather than keys. Being an operator, `.`
+" runs it again at the new cursor.
+function! s:edit(type)
+  call s:make(s:answer(s:Edit))
+endfunction
+
+" Yanks make their edit right away: like `y`, they are not repeated by `.`.
+function! s:yank()
+  call s:make(s:answer(s:Edit))
 endfunction
 
-function! s:yaninhere(...)
-  call rpcnotify(s:appJobId, s:YanInHere, a:)
+" The operator argument rewrites go through, like s:edit.
+function! s:rewrite(type)
+  call s:make(s:answer(s:Test))
 endfunction
 
-function! s:yanarhere(...)
-  call rpcnotify(s:appJobId, s:YanArHere, a:)
+" Make the `calls` the plugin sent back, a list of [method, args].
+function! s:make(calls)
+  for [method, args] in a:calls
+    call call(method, args)
+  endfor
 endfunction
 
 call s:connect()
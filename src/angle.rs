@@ -0,0 +1,113 @@
+/// Tells generic and template angle brackets apart from operators on a single line.
+///
+/// HashMap<String, Vec<u8>>      -> both pairs
+/// collect::<Vec<_>>()           -> both pairs
+/// template <typename T>         -> pair
+/// if a < b && x >> 2 > y        -> nothing
+/// fn f() -> u8 { x => y }       -> nothing
+///
+/// A `<` opens a candidate when it sticks to an identifier (or `::`, or follows `template`), and
+/// it is not part of `<=`, `<<` or `<-`. A `>` closes the innermost candidate unless it is part
+/// of `->`, `=>` or `>=`. Candidates are dropped when something that can't live inside a generic
+/// argument list shows up (`;`, braces, `&&`, `||`, or a paren/square bracket closing one that
+/// was opened before them), and whatever is left unclosed at the end of the line is dropped too,
+/// so only balanced pairs are returned.
+///
+/// Returns (byte offset, is_open) for every `<` and `>` that is a bracket, in order.
+pub fn angle_brackets(line: &str) -> Vec<(usize, bool)> {
+
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let at = |i: usize| chars.get(i).map(|(_, ch)| *ch);
+    let before = |i: usize| if i == 0 { None } else { at(i - 1) };
+
+    // Pending openers: (index in chars, nesting depth of ( and [ when it was found).
+    let mut pending: Vec<(usize, usize)> = Vec::new();
+    let mut found: Vec<(usize, bool)> = Vec::new();
+    let mut depth: usize = 0;
+
+    for (i, &(b, ch)) in chars.iter().enumerate() {
+        match ch {
+            '<' => {
+                let operator = matches!(at(i + 1), Some('=') | Some('<') | Some('-') | Some(' ') | None)
+                    || before(i) == Some('<');
+                if !operator && sticks_to_type(&chars, i) {
+                    pending.push((i, depth));
+                }
+            },
+            '>' => {
+                let operator = matches!(before(i), Some('-') | Some('=')) || at(i + 1) == Some('=');
+                if !operator {
+                    if let Some((open, _)) = pending.pop() {
+                        found.push((chars[open].0, true));
+                        found.push((b, false));
+                    }
+                }
+            },
+            '(' | '[' => depth += 1,
+            ')' | ']' => {
+                depth = depth.saturating_sub(1);
+                while pending.last().is_some_and(|(_, d)| *d > depth) {
+                    pending.pop();
+                }
+            },
+            ';' | '{' | '}' => pending.clear(),
+            '&' | '|' if at(i + 1) == Some(ch) => pending.clear(),
+            _ => {},
+        }
+    }
+
+    found.sort_unstable();
+    found
+
+}
+
+/// Whether the `<` at chars[i] is attached to something that can take generic arguments.
+fn sticks_to_type(chars: &[(usize, char)], i: usize) -> bool {
+    if i == 0 {
+        return false
+    }
+    let prev = chars[i - 1].1;
+    if prev.is_alphanumeric() || prev == '_' {
+        return true
+    }
+    if prev == ':' && i >= 2 && chars[i - 2].1 == ':' {
+        return true
+    }
+    // `template <typename T>`
+    let mut word: Vec<char> = chars[..i].iter().rev()
+        .map(|(_, ch)| *ch)
+        .skip_while(|ch| ch.is_whitespace())
+        .take_while(|ch| ch.is_alphanumeric())
+        .collect();
+    word.reverse();
+    word.into_iter().collect::<String>() == "template"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generics_are_pairs() {
+        // HashMap<String, Vec<u8>>
+        // 0      7           19  23
+        assert_eq!(angle_brackets("HashMap<String, Vec<u8>>"), vec![(7, true), (19, true), (22, false), (23, false)]);
+        // collect::<Vec<_>>()
+        // 0        9   13
+        assert_eq!(angle_brackets("collect::<Vec<_>>()"), vec![(9, true), (13, true), (15, false), (16, false)]);
+    }
+
+    #[test]
+    fn template_is_a_pair() {
+        // template <typename T>
+        // 0        9          20
+        assert_eq!(angle_brackets("template <typename T>"), vec![(9, true), (20, false)]);
+    }
+
+    #[test]
+    fn operators_are_no_pairs() {
+        assert_eq!(angle_brackets("if a < b && x >> 2 > y"), vec![]);
+        assert_eq!(angle_brackets("fn f() -> u8 { x => y }"), vec![]);
+    }
+
+}
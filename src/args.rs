@@ -2,8 +2,8 @@ use crate::utils::Pos;
 use crate::utils::BPairs;
 
 use crate::utils::{Log, Logger};
+use crate::lexer::Span;
 
-use neovim_lib::{Value};
 use unicode_segmentation::UnicodeSegmentation;
 
 
@@ -67,14 +67,14 @@ impl Logger for Args {
 
 impl Args {
 
-    pub fn new(raw_lines: Value, beg_pos: Pos, end_pos: Pos, ext_logger: &mut Option<Log>) -> Args {
+    pub fn new(lines_vec: &[String], skipped: &[&[Span]], beg_pos: Pos, end_pos: Pos, nesting: &[BPairs], ext_logger: &mut Option<Log>) -> Args {
 
-        let lines_vec = Args::unwrap_raw_lines(&raw_lines);
-        let lines: Vec<Vec<String>>  = Args::parse_lines(&lines_vec);
+        let lines: Vec<Vec<String>>  = Args::parse_lines(lines_vec);
+        let code = Args::parse_code(lines_vec, skipped);
         let pos_vec = Args::find_pos(&lines, beg_pos);
         if let Some(logger) = ext_logger { logger.log("Inside Args::new\n"); }
-        let (args, counts) = Args::parse_args(&lines, beg_pos,
-                                              end_pos, ext_logger);
+        let (args, counts) = Args::parse_args(&lines, &code, beg_pos,
+                                              end_pos, nesting, ext_logger);
 
         // assert_eq![pos_vec.len(), lines_vec.len()];
         // assert_eq![lines_vec.len(), lines.len()];
@@ -93,6 +93,42 @@ impl Args {
 
     }
 
+    /// Args whose bounds are already known, like the children of a syntax tree node. nodes are the
+    /// (first byte, end) positions of every argument, the end being one byte past the last one.
+    pub fn from_nodes(lines_vec: &[String], nodes: &[(Pos, Pos)], beg_pos: Pos, end_pos: Pos) -> Args {
+
+        let lines: Vec<Vec<String>> = Args::parse_lines(lines_vec);
+        let pos_vec = Args::find_pos(&lines, beg_pos);
+        let line_of = |n: u64| &lines_vec[(n - beg_pos.line()) as usize][..];
+
+        let args: Vec<String> = nodes.iter().map(|(start, end)| {
+            let (sc, ec) = (start.col() as usize - 1, end.col() as usize - 1);
+            if start.line() == end.line() {
+                return line_of(start.line())[sc..ec].to_string()
+            }
+            let mut text = vec![&line_of(start.line())[sc..]];
+            text.extend((start.line() + 1..end.line()).map(line_of));
+            text.push(&line_of(end.line())[..ec]);
+            text.join("\n")
+        }).collect();
+
+        let counts: Vec<usize> = (0..lines.len())
+            .map(|i| nodes.iter().filter(|(start, _)| start.line() == beg_pos.line() + i as u64).count())
+            .collect();
+
+        Args {
+            debug: vec![pos_vec.len(), lines_vec.len(), lines.len(), counts.len()],
+            lines,
+            pos_vec,
+            args,
+            counts,
+            beg_pos,
+            end_pos,
+            logger: Some(Log::new("/tmp/delinhere_arg.log")),
+        }
+
+    }
+
     fn debug_1(&self) -> String {
         let print_str = self.pos_vec.iter()
             .enumerate()
@@ -102,17 +138,6 @@ impl Args {
         print_str
     }
 
-    pub fn unwrap_raw_lines(array_value: &Value) -> Vec<String> {
-        if let Some(array) = array_value.as_array() {
-            array.iter().map(
-                |v| v.as_str().unwrap_or("").to_string()
-            ).collect()
-        }
-        else {
-            vec![String::new()]
-        }
-    }
-
     fn first_not_whitespace(utf8_vec: &Vec<String>) -> Option<usize> {
         for (i, s) in utf8_vec.iter().enumerate() {
             if s != " " { return Some(i) }
@@ -120,7 +145,7 @@ impl Args {
         None
     }
 
-    pub fn parse_lines(lines_vec: &Vec<String>) -> Vec<Vec<String>> {
+    pub fn parse_lines(lines_vec: &[String]) -> Vec<Vec<String>> {
         // split_every_line:
         let sel: Vec<Vec<String>> = lines_vec.iter()
             .map(|s|
@@ -129,6 +154,18 @@ impl Args {
         sel
     }
 
+    /// Same shape as parse_lines: whether each char is code, from the spans of every line that are
+    /// strings or comments.
+    pub fn parse_code(lines_vec: &[String], skipped: &[&[Span]]) -> Vec<Vec<bool>> {
+        lines_vec.iter().enumerate()
+            .map(|(i, s)| {
+                let spans = skipped.get(i).copied().unwrap_or(&[]);
+                UnicodeSegmentation::grapheme_indices(&s[..], true)
+                    .map(|(b, _)| !spans.iter().any(|span| span.contains(b)))
+                    .collect()
+            }).collect()
+    }
+
     /// Find first non-whitespace character position of every line and then return the Pos vector.
     fn find_pos(parsed_lines: &Vec<Vec<String>>, beg_pos: Pos) -> Vec<Pos> {
 
@@ -173,7 +210,12 @@ impl Args {
     ///    )\n
     ///
     ///
-    fn reprocessed_args(arg_chars: &Vec<String>, ext_logger: &mut Option<Log>) -> (Vec<String>, Vec<usize>) {
+    ///
+    /// nesting: the pairs whose commas don't split arguments, the BPairs::array() ones and the
+    /// custom pairs of the buffer. Their delimiters may be more than one char long.
+    /// code: for each of arg_chars, whether it is code. Brackets and commas in strings and
+    /// comments are plain text.
+    fn reprocessed_args(arg_chars: &[String], code: &[bool], nesting: &[BPairs], ext_logger: &mut Option<Log>) -> (Vec<String>, Vec<usize>) {
         // arg_chars contains every character (as a String) from the starting bracket pair to the
         // end bracket pair.
         // if let Some(logger) = ext_logger { logger.log("Inside Args::reprocessed_args\n"); }
@@ -181,17 +223,23 @@ impl Args {
 
         let N = arg_chars.len();
 
-        fn ch_is_open_bpair(ch: &String) -> Option<BPairs> {
-            for bpair in &BPairs::array() {
-                if *ch == bpair.to_simple_string_open() {
-                    return Some(bpair.clone())
-                }
+        // Number of chars of `delimiter` if arg_chars[i..] starts with it.
+        fn delimiter_at(arg_chars: &[String], i: usize, delimiter: &str) -> Option<usize> {
+            let mut rest = delimiter;
+            let mut k = 0;
+            while !rest.is_empty() {
+                rest = rest.strip_prefix(&arg_chars.get(i + k)?[..])?;
+                k += 1;
             }
-            None
+            Some(k)
         }
 
-        fn ch_is_close_bpair(ch: &String, bpair: &BPairs) -> bool {
-            bpair.to_simple_string_close() == *ch
+        fn open_bpair_at(arg_chars: &[String], i: usize, nesting: &[BPairs]) -> Option<(BPairs, usize)> {
+            nesting.iter()
+                .filter_map(|bpair| {
+                    delimiter_at(arg_chars, i, &bpair.to_simple_string_open()).map(|k| (bpair.clone(), k))
+                })
+                .max_by_key(|(_, k)| *k)
         }
 
         fn add_char_2_last_buf(bufs: &mut Vec<(BPairs, String)>, chars: &str) {
@@ -243,56 +291,67 @@ impl Args {
         let mut count: usize = 1;
 
         if let Some(logger) = ext_logger { logger.log(&format!("arg_cahrs := {:?}\n", arg_chars)); }
-        for i in 0..N {
+        let mut i = 0;
+        while i < N {
             if let Some(logger) = ext_logger { logger.log(&format!("i: {}\n", i)); }
             let ch = &arg_chars[i];
-            if let Some(bpair) = ch_is_open_bpair(&ch) {
+
+            if !code[i] {
+                if bufs.is_empty() { add_char_2_arg(&mut curr_arg, ch) } else { add_char_2_last_buf(&mut bufs, ch) }
+                i += 1;
+                continue
+            }
+
+            // Closing the innermost buf goes first, custom pairs may close with their opener.
+            let closing = bufs.last()
+                .and_then(|(bpair, _)| delimiter_at(arg_chars, i, &bpair.to_simple_string_close()));
+            if let Some(k) = closing {
+                if let Some(logger) = ext_logger { logger.log(&format!("Found closing bpair {:?}\n", bufs.last())); }
+                close_buf(&mut bufs, &mut curr_arg, &arg_chars[i..i + k].concat(), ext_logger);
+                i += k;
+                continue
+            }
+
+            if let Some((bpair, k)) = open_bpair_at(arg_chars, i, nesting) {
                 if let Some(logger) = ext_logger { logger.log(&format!("Found open bpair {:?}\n", bpair)); }
-                new_buf(&mut bufs, &bpair, ch);
+                new_buf(&mut bufs, &bpair, &arg_chars[i..i + k].concat());
+                i += k;
+                continue
             }
-            else {
-                if bufs.len() == 0 {
-                    if let Some(logger) = ext_logger { logger.log("bufs len == 0\n"); }
-
-                    // We can add directly to the arg
-                    if *ch == "," {
-                        // new arg
-                        if let Some(logger) = ext_logger { logger.log("New Arg\n"); }
-                        parsed_args.push(curr_arg);
-                        count += 1;
-                        curr_arg = String::new();
-                    }
-                    else if *ch == "\n" {
-                        // If we find ourselves in numbre 6, we discount
-                        if curr_arg.trim() == "" {
-                            count -= 1;
-                        }
-                        if let Some(logger) = ext_logger { logger.log(&format!("Found newline! count:= {}\n", count)); }
-                        count_args.push(count);
-                        count = 1;
-                    }
-                    else {
-                        if let Some(logger) = ext_logger { logger.log(&format!("Adding char '{}'\n", ch)); }
-                        add_char_2_arg(&mut curr_arg, ch)
-                    }
 
-                }
-                else {
+            if bufs.is_empty() {
+                if let Some(logger) = ext_logger { logger.log("bufs len == 0\n"); }
 
-                    if let Some(logger) = ext_logger { logger.log(&format!("bufs len == {}\n", bufs.len())); }
-                    let (bpair, _) = &bufs[bufs.len()-1];
-                    if ch_is_close_bpair(&ch, bpair) {
-                        if let Some(logger) = ext_logger { logger.log(&format!("Found closing bpair {:?}\n", bpair)); }
-                        close_buf(&mut bufs, &mut curr_arg, ch, ext_logger)
-                    }
-                    else {
-                        if let Some(logger) = ext_logger { logger.log("No bpair\n"); }
-                        // We need to append to last buf
-                        add_char_2_last_buf(&mut bufs, ch)
+                // We can add directly to the arg
+                if *ch == "," {
+                    // new arg
+                    if let Some(logger) = ext_logger { logger.log("New Arg\n"); }
+                    parsed_args.push(curr_arg);
+                    count += 1;
+                    curr_arg = String::new();
+                }
+                else if *ch == "\n" {
+                    // If we find ourselves in numbre 6, we discount
+                    if curr_arg.trim() == "" {
+                        count -= 1;
                     }
-
+                    if let Some(logger) = ext_logger { logger.log(&format!("Found newline! count:= {}\n", count)); }
+                    count_args.push(count);
+                    count = 1;
                 }
+                else {
+                    if let Some(logger) = ext_logger { logger.log(&format!("Adding char '{}'\n", ch)); }
+                    add_char_2_arg(&mut curr_arg, ch)
+                }
+
+            }
+            else {
+                if let Some(logger) = ext_logger { logger.log(&format!("bufs len == {}, no bpair\n", bufs.len())); }
+                // We need to append to last buf
+                add_char_2_last_buf(&mut bufs, ch)
             }
+
+            i += 1;
         }
 
         // Collecting residual bufs.
@@ -322,26 +381,44 @@ impl Args {
         (parsed_args, count_args)
     }
 
-    pub fn parse_args(lines: &Vec<Vec<String>>, beg_pos: Pos, end_pos: Pos, ext_logger: &mut Option<Log>) -> (Vec<String>, Vec<usize>) {
+    /// Index of the grapheme of `graphemes` starting at byte `byte` of their line, or of the one
+    /// after it when it is within a grapheme.
+    fn grapheme_at(graphemes: &[String], byte: usize) -> usize {
+        let mut start = 0;
+        graphemes.iter().take_while(|g| {
+            let before = start < byte;
+            start += g.len();
+            before
+        }).count()
+    }
+
+    pub fn parse_args(lines: &[Vec<String>], code: &[Vec<bool>], beg_pos: Pos, end_pos: Pos, nesting: &[BPairs], ext_logger: &mut Option<Log>) -> (Vec<String>, Vec<usize>) {
         if let Some(logger) = ext_logger { logger.log("Inside Args::parse_args\n"); }
         let n_lines = lines.len();
-        let (_bl, bc) = beg_pos.get();
-        let (_el, ec) = end_pos.get();
+        // The positions are byte columns, the lines are graphemes. bc is the index of the first
+        // grapheme after the opening delimiter and ec - 1 the one of the closing delimiter.
+        let bc = Args::grapheme_at(&lines[0], beg_pos.col() as usize);
+        let ec = Args::grapheme_at(&lines[n_lines - 1], end_pos.col() as usize - 1) + 1;
 
         let mut print_str = String::new();
         let mut only_args = Vec::new();
+        let mut only_code = Vec::new();
 
         for (i, vs) in lines.iter().enumerate() {
             let mut slice = &vs[..];
+            let mut code_slice = &code[i][..];
 
             if n_lines == 1 {
-                slice = &vs[bc as usize .. ec as usize -1]
+                slice = &vs[bc .. ec -1];
+                code_slice = &code[i][bc .. ec -1];
             }
             else if i == 0 {
-                slice = &vs[bc as usize ..]
+                slice = &vs[bc ..];
+                code_slice = &code[i][bc ..];
             }
             else if i == n_lines -1 {
-                slice = &vs[.. ec as usize -1];
+                slice = &vs[.. ec -1];
+                code_slice = &code[i][.. ec -1];
                 if let Some(logger) = ext_logger { logger.log(&format!("Last line, {:?}\n", slice)); }
             }
 
@@ -349,9 +426,11 @@ impl Args {
             for stuff in slice {
                 only_args.push(stuff.clone())
             }
+            only_code.extend_from_slice(code_slice);
 
             if i < n_lines -1 {
                 only_args.push("\n".to_string());
+                only_code.push(true);
             }
 
             print_str.push_str(
@@ -360,7 +439,7 @@ impl Args {
 
         }
 
-        let reprocessed_args = Args::reprocessed_args(&only_args, ext_logger);
+        let reprocessed_args = Args::reprocessed_args(&only_args, &only_code, nesting, ext_logger);
         reprocessed_args
 
     }
@@ -439,3 +518,43 @@ impl Args {
 
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multibyte_custom_pair() {
+        // f«é, b»
+        // bytes: f 1, « 2-3, é 4-5, `,` 6, ` ` 7, b 8, » 9-10
+        let lines = vec!["f«é, b»".to_string()];
+        let nesting = [BPairs::Custom("«".to_string(), "»".to_string())];
+        let mut args = Args::new(&lines, &[&[]], Pos::new(1, 3), Pos::new(1, 9), &nesting, &mut None);
+        assert_eq!(args.args, vec!["é", "b"]);
+        assert_eq!(args.reconstruct_args(), "é, b");
+
+        let lines = vec!["f«é,".to_string(), "  b»".to_string()];
+        let args = Args::new(&lines, &[&[], &[]], Pos::new(1, 3), Pos::new(2, 4), &nesting, &mut None);
+        assert_eq!(args.args, vec!["é", "b"]);
+    }
+
+    #[test]
+    fn arguments_of_syntax_tree_nodes() {
+        // f(a, [1,
+        //   2], b)
+        // 12345678
+        let lines = vec!["f(a, [1,".to_string(), "  2], b)".to_string()];
+        let nodes = [
+            (Pos::new(1, 3), Pos::new(1, 4)),
+            (Pos::new(1, 6), Pos::new(2, 5)),
+            (Pos::new(2, 7), Pos::new(2, 8)),
+        ];
+        let args = Args::from_nodes(&lines, &nodes, Pos::new(1, 2), Pos::new(2, 8));
+        assert_eq!(args.args, vec!["a", "[1,\n  2]", "b"]);
+        assert_eq!(args.counts, vec![2, 1]);
+
+        let args = Args::from_nodes(&lines[..1], &[], Pos::new(1, 2), Pos::new(1, 9));
+        assert!(args.args.is_empty());
+    }
+
+}
@@ -0,0 +1,65 @@
+use crate::args::Args;
+use crate::scanner::{Pair, Scanner};
+use crate::treesitter::Node;
+use crate::utils::{BPairs, Log, Pos};
+
+/// Where pairs and their arguments come from. Every command goes through one of these, chosen per
+/// buffer: the syntax tree when the filetype asks for it and neovim could tell, the text
+/// otherwise.
+pub trait Backend {
+
+    /// Every pair around the text from `start` to `end`, innermost first. For a cursor both are
+    /// the cursor.
+    fn stack(&self, start: Pos, end: Pos) -> Vec<Pair>;
+
+    /// The arguments between the delimiters of `pair`.
+    fn args(&self, pair: &Pair, logger: &mut Option<Log>) -> Args;
+
+}
+
+/// Pairs found by scanning the text, see `scanner::index_line`.
+///
+/// nesting: the pairs whose commas don't split arguments.
+pub struct Text<'a> {
+    pub scanner: &'a Scanner<'a>,
+    pub nesting: &'a [BPairs],
+}
+
+impl Backend for Text<'_> {
+
+    fn stack(&self, start: Pos, end: Pos) -> Vec<Pair> {
+        self.scanner.stack(start, end)
+    }
+
+    fn args(&self, pair: &Pair, logger: &mut Option<Log>) -> Args {
+        let between = self.scanner.lines_between(pair.open_end.line(), pair.close.line());
+        let skipped = self.scanner.skipped_between(pair.open_end.line(), pair.close.line());
+        Args::new(between, &skipped, pair.open_end, pair.close, self.nesting, logger)
+    }
+
+}
+
+/// The nodes of the syntax tree neovim found around the cursor, innermost first, see
+/// `treesitter::QUERY`. They were looked up for the cursor of the command, which is within any
+/// text `stack` is asked about, so only the ones around all of it are kept.
+pub struct TreeSitter<'a> {
+    pub scanner: &'a Scanner<'a>,
+    pub nodes: &'a [Node],
+}
+
+impl Backend for TreeSitter<'_> {
+
+    fn stack(&self, start: Pos, end: Pos) -> Vec<Pair> {
+        self.nodes.iter()
+            .map(|node| node.pair.clone())
+            .filter(|pair| pair.open <= start && self.scanner.around(pair).1 >= end)
+            .collect()
+    }
+
+    fn args(&self, pair: &Pair, _logger: &mut Option<Log>) -> Args {
+        let between = self.scanner.lines_between(pair.open_end.line(), pair.close.line());
+        let node = self.nodes.iter().find(|node| node.pair.open == pair.open && node.pair.close == pair.close);
+        Args::from_nodes(between, node.map_or(&[][..], |node| &node.args[..]), pair.open_end, pair.close)
+    }
+
+}
@@ -0,0 +1,63 @@
+use neovim_lib::{Neovim, NeovimApi, Value};
+
+/// A list of API calls sent to neovim with a single `nvim_call_atomic`, so a command costs one
+/// round-trip no matter how many calls it needs.
+#[derive(Debug, Default)]
+pub struct Batch {
+    calls: Vec<Value>,
+}
+
+impl Batch {
+
+    pub fn new() -> Batch {
+        Batch { calls: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Queue a call to any `nvim_*` API method.
+    pub fn push(&mut self, method: &str, args: Vec<Value>) {
+        self.calls.push(Value::from(vec![Value::from(method), Value::from(args)]));
+    }
+
+    /// Queue a call to a vimscript function, through `nvim_call_function`.
+    pub fn call_function(&mut self, fname: &str, args: Vec<Value>) {
+        self.push("nvim_call_function", vec![Value::from(fname), Value::from(args)]);
+    }
+
+    pub fn eval(&mut self, expr: &str) {
+        self.push("nvim_eval", vec![Value::from(expr)]);
+    }
+
+    pub fn feedkeys(&mut self, keys: &str, mode: &str) {
+        self.push("nvim_feedkeys", vec![Value::from(keys), Value::from(mode), Value::from(false)]);
+    }
+
+    /// The queued calls as `[method, args]` lists, for vimscript to make them itself when
+    /// neovim is waiting on a request and can't be called.
+    pub fn into_calls(self) -> Vec<Value> {
+        self.calls
+    }
+
+    /// Send every queued call. On success there is one result per call, in order. neovim stops
+    /// at the first failing call, which is reported with its index.
+    pub fn send(self, nvim: &mut Neovim) -> Result<Vec<Value>, String> {
+
+        let response = nvim.call_atomic(self.calls).map_err(|err| format!("{:?}", err))?;
+        let mut response = response.into_iter();
+
+        let results = response.next().and_then(|r| match r {
+            Value::Array(results) => Some(results),
+            _ => None,
+        }).ok_or_else(|| String::from("malformed nvim_call_atomic response"))?;
+
+        match response.next() {
+            Some(Value::Nil) | None => Ok(results),
+            Some(err) => Err(format!("{}", err)),
+        }
+
+    }
+
+}
@@ -0,0 +1,129 @@
+use neovim_lib::Value;
+
+use crate::scanner::{Pair, Target};
+
+/// What a `Here` message asks for, decoded from its payload, a dictionary like
+///
+///     {'verb': 'gU', 'adverb': 'i', 'count': 2, 'register': 'a', 'target': 'next', 'pairs': ['(']}
+///
+/// where every key can be left out. Text objects come without a verb, the operator being already
+/// pending.
+///
+/// verb: the keys of an operator, fed as they are, so any of them works: "d", "gU", "zf". "v"
+/// leaves the pair selected.
+/// adverb: "i" for the inside of the pair, "a" for all of it. Defaults to "i".
+/// count: which of the pairs, 1 for the first one. Defaults to 1.
+/// register: the register to use, as in `v:register`. Empty for the default one.
+/// target: where the pairs are, see `Target::from_arg`.
+/// pairs: only pairs opening with one of these delimiters count. Any pair when empty.
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub verb: String,
+    pub adverb: String,
+    pub count: u64,
+    pub register: String,
+    pub target: Target,
+    pub pairs: Vec<String>,
+}
+
+impl Command {
+
+    /// Decode a payload. None when it isn't a dictionary.
+    pub fn new(payload: &Value) -> Option<Command> {
+        let payload = payload.as_map()?;
+        let field = |key: &str| payload.iter().find(|(k, _)| k.as_str() == Some(key)).map(|(_, v)| v);
+        let string = |key: &str| field(key).and_then(Value::as_str).unwrap_or("").to_string();
+
+        let verb = string("verb");
+        let adverb = if string("adverb") == "a" { String::from("a") } else { String::from("i") };
+        let count = field("count").and_then(Value::as_u64).unwrap_or(1).max(1);
+        let pairs = field("pairs")
+            .and_then(Value::as_array)
+            .map_or(Vec::new(), |pairs| pairs.iter().filter_map(Value::as_str).map(String::from).collect());
+
+        Some(Command { verb, adverb, count, register: string("register"), target: Target::from_arg(&string("target")), pairs })
+    }
+
+    /// The payload `new` decodes back into this command.
+    pub fn to_value(&self) -> Value {
+        Value::Map(vec![
+            (Value::from("verb"), Value::from(self.verb.as_str())),
+            (Value::from("adverb"), Value::from(self.adverb.as_str())),
+            (Value::from("count"), Value::from(self.count)),
+            (Value::from("register"), Value::from(self.register.as_str())),
+            (Value::from("target"), Value::from(self.target.to_arg())),
+            (Value::from("pairs"), Value::from(self.pairs.iter().map(|p| Value::from(p.as_str())).collect::<Vec<Value>>())),
+        ])
+    }
+
+    /// The pair of `targets` the command is about: the one its count picks among those it accepts.
+    pub fn pick(&self, targets: impl IntoIterator<Item = Pair>) -> Option<Pair> {
+        targets.into_iter().filter(|pair| self.accepts(pair)).nth(self.count as usize - 1)
+    }
+
+    /// Whether `pair` is one of the pairs asked for.
+    pub fn accepts(&self, pair: &Pair) -> bool {
+        self.pairs.is_empty() || self.pairs.contains(&pair.bpair.to_simple_string_open())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{BPairs, Pos};
+
+    /// The payload `s:herecommand` sends for `:2DIHHere gU i` followed by `args`.
+    fn payload(target: &str, pairs: &[&str]) -> Value {
+        Value::Map(vec![
+            (Value::from("verb"), Value::from("gU")),
+            (Value::from("adverb"), Value::from("i")),
+            (Value::from("count"), Value::from(2)),
+            (Value::from("target"), Value::from(target)),
+            (Value::from("pairs"), Value::from(pairs.iter().map(|p| Value::from(*p)).collect::<Vec<Value>>())),
+        ])
+    }
+
+    #[test]
+    fn new_reads_the_target_and_the_pairs() {
+        // :2DIHHere gU i next ( [
+        let command = Command::new(&payload("next", &["(", "["])).unwrap();
+        assert_eq!((&command.verb[..], &command.adverb[..], command.count), ("gU", "i", 2));
+        assert_eq!(command.target, Target::Next);
+        assert_eq!(command.pairs, vec!["(", "["]);
+        // :2DIHHere gU i ( [
+        let command = Command::new(&payload("", &["(", "["])).unwrap();
+        assert_eq!(command.target, Target::Here);
+        assert_eq!(command.pairs, vec!["(", "["]);
+    }
+
+    #[test]
+    fn to_value_reads_back_as_the_same_command() {
+        let command = Command::new(&payload("last", &["{"])).unwrap();
+        let back = Command::new(&command.to_value()).unwrap();
+        assert_eq!((&back.verb, &back.adverb, back.count), (&command.verb, &command.adverb, command.count));
+        assert_eq!((&back.register, back.target, &back.pairs), (&command.register, command.target, &command.pairs));
+    }
+
+    fn pair(bpair: BPairs, col: u64) -> Pair {
+        Pair { bpair, open: Pos::new(1, col), open_end: Pos::new(1, col), close: Pos::new(2, 1), close_len: 1 }
+    }
+
+    #[test]
+    fn pick_counts_only_the_pairs_accepted() {
+        let targets = || vec![pair(BPairs::Paren, 3), pair(BPairs::Brack, 2), pair(BPairs::Paren, 1)];
+        // :2DIHHere gU i ( [ counts both kinds, :2DIHHere gU i ( only parentheses.
+        let command = Command::new(&payload("", &["(", "["])).unwrap();
+        assert!(command.accepts(&pair(BPairs::Brack, 1)) && !command.accepts(&pair(BPairs::Curly, 1)));
+        assert_eq!(command.pick(targets()).map(|p| p.open.col()), Some(2));
+        let command = Command::new(&payload("", &["("])).unwrap();
+        assert_eq!(command.pick(targets()).map(|p| p.open.col()), Some(1));
+        let command = Command::new(&payload("", &["{"])).unwrap();
+        assert_eq!(command.pick(targets()).map(|p| p.open.col()), None);
+        // Without delimiters, any pair counts.
+        let command = Command::new(&payload("", &[])).unwrap();
+        assert!(command.accepts(&pair(BPairs::Curly, 1)));
+        assert_eq!(command.pick(targets()).map(|p| p.open.col()), Some(2));
+    }
+
+}
@@ -0,0 +1,136 @@
+/// Comment delimiters of a buffer, read from its `'comments'` and `'commentstring'` options.
+///
+/// blocks: (start, end) of block comments, `/*` and `*/`.
+/// leaders: (leader, blank) of line comments, longest first so `///` is found before `//`. A
+/// leader with blank set only starts a comment when a blank or the end of the line follows it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Comments {
+    pub blocks: Vec<(String, String)>,
+    pub leaders: Vec<(String, bool)>,
+}
+
+/// The global default of `'comments'`, what a buffer gets when no filetype plugin sets it. It
+/// mixes C, shell, TeX and mail comments and says nothing about the buffer, so `%` in `a % b`
+/// would be a comment.
+const DEFAULT: &str = "s1:/*,mb:*,ex:*/,://,b:#,:%,:XCOMM,n:>,fb:-";
+
+impl Comments {
+
+    /// `comments` is a comma separated list of {flags}:{string}. Three-piece comments are an `s`
+    /// part, maybe an `m` part and an `e` part, the start and end of a block. Parts with none of
+    /// the s, m, e, f or n flags are line comment leaders, and the b flag asks for a blank after
+    /// them. For C that gives
+    ///
+    ///     s1:/*,mb:*,ex:*/,://       ->  blocks [("/*", "*/")], leaders [("//", false)]
+    ///
+    /// Nested comments (`n:>` in mail) and first lines (`fb:-` for lists) are only comments at the
+    /// start of a line, and leaders made of letters only (`XCOMM`) would be found in any word, so
+    /// neither are leaders. The default value of the option is ignored, see `DEFAULT`.
+    ///
+    /// `commentstring` ("/*%s*/", "-- %s") adds its block or leader if it wasn't there already.
+    pub fn parse(comments: &str, commentstring: &str) -> Comments {
+
+        let mut parsed = Comments::default();
+        let mut start: Option<String> = None;
+        let comments = if comments == DEFAULT { "" } else { comments };
+
+        for part in split_unescaped(comments) {
+            let (flags, string) = match part.find(':') {
+                Some(i) => (&part[..i], part[i + 1..].trim().to_string()),
+                None => continue,
+            };
+            if string.is_empty() {
+                continue
+            }
+            if flags.contains('s') {
+                start = Some(string);
+            }
+            else if flags.contains('e') {
+                if let Some(start) = start.take() {
+                    parsed.add_block(start, string);
+                }
+            }
+            else if !flags.contains(['m', 'f', 'n']) && !string.chars().all(char::is_alphabetic) {
+                parsed.add_leader(string, flags.contains('b'));
+            }
+        }
+
+        if let Some(i) = commentstring.find("%s") {
+            let (left, right) = (commentstring[..i].trim(), commentstring[i + 2..].trim());
+            if !left.is_empty() && !right.is_empty() {
+                parsed.add_block(left.to_string(), right.to_string());
+            }
+            else if !left.is_empty() {
+                parsed.add_leader(left.to_string(), false);
+            }
+        }
+
+        parsed.leaders.sort_by_key(|(l, _)| std::cmp::Reverse(l.len()));
+        parsed
+
+    }
+
+    pub fn add_block(&mut self, start: String, end: String) {
+        let block = (start, end);
+        if !self.blocks.contains(&block) {
+            self.blocks.push(block);
+        }
+    }
+
+    fn add_leader(&mut self, leader: String, blank: bool) {
+        if !self.leaders.iter().any(|(l, _)| *l == leader) {
+            self.leaders.push((leader, blank));
+        }
+    }
+
+}
+
+/// Split an option value on the commas that are not escaped with a backslash, unescaping the
+/// rest (`\,` and `\ `).
+fn split_unescaped(value: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => if let Some(escaped) = chars.next() { parts.last_mut().unwrap().push(escaped) },
+            ',' => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(ch),
+        }
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaders(comments: &str) -> Vec<(String, bool)> {
+        Comments::parse(comments, "").leaders
+    }
+
+    fn leader(leader: &str, blank: bool) -> (String, bool) {
+        (leader.to_string(), blank)
+    }
+
+    #[test]
+    fn three_piece_comments_are_blocks() {
+        let parsed = Comments::parse("s1:/*,mb:*,ex:*/,:///,://", "");
+        assert_eq!(parsed.blocks, vec![(String::from("/*"), String::from("*/"))]);
+        assert_eq!(leaders("s1:/*,mb:*,ex:*/,:///,://"), vec![leader("///", false), leader("//", false)]);
+    }
+
+    #[test]
+    fn only_real_leaders_are_kept() {
+        // Markdown and mail.
+        assert_eq!(leaders("fb:*,fb:-,fb:+,n:>"), vec![]);
+        assert_eq!(leaders("b:#,:XCOMM,:%"), vec![leader("#", true), leader("%", false)]);
+    }
+
+    #[test]
+    fn default_value_is_ignored() {
+        assert_eq!(Comments::parse(DEFAULT, ""), Comments::default());
+        let parsed = Comments::parse(DEFAULT, "# %s");
+        assert_eq!(parsed.leaders, vec![leader("#", false)]);
+    }
+
+}
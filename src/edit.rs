@@ -0,0 +1,149 @@
+use neovim_lib::Value;
+
+use crate::batch::Batch;
+use crate::scanner::{Pair, Scanner};
+use crate::utils::Pos;
+
+/// What a text object on `pair` covers, with `adverb` "i" or "a": its first and last char, and
+/// the Visual mode that selects it. None when there is nothing inside.
+///
+/// Like vim's `i(`, an inside made of whole lines is linewise, "V", so deleting it leaves no empty
+/// line behind. Anything else is "v".
+pub fn object(scanner: &Scanner, pair: &Pair, adverb: &str) -> Option<(Pos, Pos, &'static str)> {
+    if adverb == "a" {
+        let (start, end) = scanner.around(pair);
+        return Some((start, end, "v"))
+    }
+    if let Some((first, last)) = scanner.inside_lines(pair) {
+        return Some((Pos::new(first, 1), Pos::new(last, 1), "V"))
+    }
+    scanner.inside(pair).map(|(start, end)| (start, end, "v"))
+}
+
+/// Queue the calls that delete, `verb` "d", or yank, "y", the text of `object` straight through
+/// the buffer API. No key is fed, so mappings, 'selection' and the mode can't get in the way. The
+/// cursor ends where the text started, like after vim's own `di(` or `yi(`.
+pub fn queue(batch: &mut Batch, scanner: &Scanner, object: (Pos, Pos, &str), verb: &str, register: &str) {
+    let text = text(scanner, object);
+    queue_register(batch, &text, object.2 == "V", verb, register);
+    if verb == "d" {
+        queue_set(batch, object, &text, vec![]);
+    }
+    queue_cursor(batch, object.0);
+}
+
+/// Queue the calls that replace the text of `object` with `replacement`, through the buffer API
+/// like `queue`. The text replaced goes to `register` like a deleted one.
+pub fn queue_replace(batch: &mut Batch, scanner: &Scanner, object: (Pos, Pos, &str), replacement: &str, register: &str) {
+    let text = text(scanner, object);
+    queue_register(batch, &text, object.2 == "V", "d", register);
+    queue_set(batch, object, &text, replacement.split('\n').map(String::from).collect());
+    queue_cursor(batch, object.0);
+}
+
+/// The text of `object`, line by line. Whole lines when it is linewise.
+fn text(scanner: &Scanner, (start, end, mode): (Pos, Pos, &str)) -> Vec<String> {
+    if mode == "V" {
+        scanner.lines_between(start.line(), end.line()).to_vec()
+    }
+    else {
+        scanner.text(start, end)
+    }
+}
+
+/// Queue the call that sets the text of `object`, currently `text`, to `lines`.
+fn queue_set(batch: &mut Batch, (start, end, mode): (Pos, Pos, &str), text: &[String], lines: Vec<String>) {
+    let lines = lines.into_iter().map(Value::from).collect::<Vec<Value>>();
+    if mode == "V" {
+        batch.push("nvim_buf_set_lines", vec![
+            Value::from(0), Value::from(start.line() - 1), Value::from(end.line()), Value::from(true),
+            Value::from(lines),
+        ]);
+        return
+    }
+    // The API wants at least an empty line, and the end of the text one byte past its last char.
+    let lines = if lines.is_empty() { vec![Value::from("")] } else { lines };
+    let last_len = text.last().map_or(0, |line| line.len() as u64);
+    let end_col = if start.line() == end.line() { start.col() - 1 + last_len } else { last_len };
+    batch.push("nvim_buf_set_text", vec![
+        Value::from(0), Value::from(start.line() - 1), Value::from(start.col() - 1),
+        Value::from(end.line() - 1), Value::from(end_col), Value::from(lines),
+    ]);
+}
+
+fn queue_cursor(batch: &mut Batch, pos: Pos) {
+    let cursor = vec![Value::from(pos.line()), Value::from(pos.col() - 1)];
+    batch.push("nvim_win_set_cursor", vec![Value::from(0), Value::from(cursor)]);
+}
+
+/// Queue the register writes of `verb` for `text`, the way vim does them. A yank goes to "0. A
+/// delete goes to "1, after shifting the numbered registers, or to "- when it is within a line.
+/// A given register gets the text instead, appended for an uppercase one, and the black hole
+/// register gets nothing. The unnamed register then points to the one written.
+fn queue_register(batch: &mut Batch, text: &[String], linewise: bool, verb: &str, register: &str) {
+
+    let register = match register {
+        "_" => return,
+        "" | "\"" if verb == "y" => "0",
+        "" | "\"" if linewise || text.len() > 1 => {
+            batch.eval("map(range(9, 2, -1), {_, n -> setreg(n, getreg(n - 1, 1, 1), getregtype(n - 1))})");
+            "1"
+        },
+        "" | "\"" => "-",
+        register => register,
+    };
+
+    let lines = text.iter().map(|line| Value::from(line.as_str())).collect::<Vec<Value>>();
+    let options = if linewise { "lu" } else { "cu" };
+    batch.call_function("setreg", vec![Value::from(register), Value::from(lines), Value::from(options)]);
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `queue_register` does for a delete or yank of `lines`: whether it shifts the numbered
+    /// registers, and the register it sets with its options.
+    fn writes(lines: &[&str], linewise: bool, verb: &str, register: &str) -> (bool, Option<(String, String)>) {
+        let text: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        let mut batch = Batch::new();
+        queue_register(&mut batch, &text, linewise, verb, register);
+        let calls = batch.into_calls();
+        let shifted = calls.iter().any(|call| call[0].as_str() == Some("nvim_eval"));
+        let set = calls.iter()
+            .find(|call| call[0].as_str() == Some("nvim_call_function"))
+            .map(|call| {
+                let args = &call[1][1];
+                (args[0].as_str().unwrap().to_string(), args[2].as_str().unwrap().to_string())
+            });
+        (shifted, set)
+    }
+
+    fn set(register: &str, options: &str) -> Option<(String, String)> {
+        Some((register.to_string(), options.to_string()))
+    }
+
+    #[test]
+    fn small_deletes_go_to_the_small_delete_register() {
+        assert_eq!(writes(&["a, b"], false, "d", ""), (false, set("-", "cu")));
+        assert_eq!(writes(&["a, b"], false, "d", "\""), (false, set("-", "cu")));
+    }
+
+    #[test]
+    fn numbered_registers_shift_for_deletes_of_lines() {
+        assert_eq!(writes(&["a,", "b"], false, "d", ""), (true, set("1", "cu")));
+        assert_eq!(writes(&["  a, b"], true, "d", ""), (true, set("1", "lu")));
+        // Yanks go to "0 and leave the numbered registers alone.
+        assert_eq!(writes(&["a,", "b"], false, "y", ""), (false, set("0", "cu")));
+    }
+
+    #[test]
+    fn given_registers() {
+        assert_eq!(writes(&["a,", "b"], false, "d", "a"), (false, set("a", "cu")));
+        // setreg() appends to an uppercase register, like vim does.
+        assert_eq!(writes(&["a, b"], false, "y", "A"), (false, set("A", "cu")));
+        assert_eq!(writes(&["a,", "b"], true, "d", "_"), (false, None));
+    }
+
+}
@@ -0,0 +1,59 @@
+use neovim_lib::{Neovim, Value};
+
+use crate::batch::Batch;
+use crate::scanner::Pair;
+
+/// The hint keys, given to the candidates in order. Candidates past the last one get no label.
+pub const KEYS: &str = "asdfghjklqwertyuiopzxcvbnm";
+
+/// Highlight group of the labels, linked to `IncSearch` by the plugin.
+const HIGHLIGHT: &str = "DelinhereHint";
+
+/// Label the opening delimiter of every pair of `pairs` with its hint key, wait for a key and
+/// clear the labels. Returns the index of the chosen pair, None when the key is no label, like
+/// `<Esc>`.
+///
+/// The labels are extmarks of `namespace` with their text over the delimiter. Everything happens
+/// in a single request: neovim redraws, then `getcharstr()` blocks it until a key comes.
+pub fn pick(nvim: &mut Neovim, namespace: i64, pairs: &[Pair]) -> Result<Option<usize>, String> {
+
+    let mut batch = Batch::new();
+    for (pair, key) in pairs.iter().zip(KEYS.chars()) {
+        let label = Value::from(vec![Value::from(vec![Value::from(key.to_string()), Value::from(HIGHLIGHT)])]);
+        let opts = Value::Map(vec![
+            (Value::from("virt_text"), label),
+            (Value::from("virt_text_pos"), Value::from("overlay")),
+        ]);
+        batch.push("nvim_buf_set_extmark", vec![
+            Value::from(0), Value::from(namespace),
+            Value::from(pair.open.line() - 1), Value::from(pair.open.col() - 1), opts,
+        ]);
+    }
+    batch.push("nvim_command", vec![Value::from("redraw")]);
+    batch.call_function("getcharstr", vec![]);
+    clear(&mut batch, namespace);
+
+    let results = match batch.send(nvim) {
+        Ok(results) => results,
+        Err(err) => {
+            // Interrupted before the labels were cleared.
+            let mut batch = Batch::new();
+            clear(&mut batch, namespace);
+            batch.send(nvim)?;
+            return Err(err)
+        }
+    };
+
+    let key = results.get(results.len() - 3).and_then(Value::as_str).unwrap_or("");
+    let mut chars = key.chars();
+    Ok(match (chars.next(), chars.next()) {
+        (Some(ch), None) => KEYS.find(ch).filter(|&i| i < pairs.len()),
+        _ => None,
+    })
+
+}
+
+fn clear(batch: &mut Batch, namespace: i64) {
+    batch.push("nvim_buf_clear_namespace", vec![Value::from(0), Value::from(namespace), Value::from(0), Value::from(-1)]);
+    batch.push("nvim_command", vec![Value::from("redraw")]);
+}
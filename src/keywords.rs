@@ -0,0 +1,117 @@
+/// Delimiters made of words: any of `openers` opens, any of `closers` closes. All openers of a
+/// group share their nesting, so in Lua
+///
+///     function f() if x then y() end end
+///
+/// the first `end` closes the `if` and the second one the `function`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeywordPair {
+    pub openers: Vec<String>,
+    pub closers: Vec<String>,
+}
+
+impl KeywordPair {
+
+    fn new(openers: &[&str], closers: &[&str]) -> KeywordPair {
+        KeywordPair {
+            openers: openers.iter().map(|s| s.to_string()).collect(),
+            closers: closers.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+}
+
+/// The keyword pairs every buffer of a filetype gets without any configuration.
+pub fn defaults(filetype: &str) -> Vec<KeywordPair> {
+    match filetype {
+        "lua" => vec![
+            KeywordPair::new(&["function", "do", "if"], &["end"]),
+            KeywordPair::new(&["repeat"], &["until"]),
+        ],
+        "sh" | "bash" | "zsh" => vec![
+            KeywordPair::new(&["if"], &["fi"]),
+            KeywordPair::new(&["case"], &["esac"]),
+            KeywordPair::new(&["do"], &["done"]),
+        ],
+        "vim" => vec![
+            KeywordPair::new(&["function", "func"], &["endfunction", "endfunc"]),
+            KeywordPair::new(&["if"], &["endif"]),
+            KeywordPair::new(&["for"], &["endfor"]),
+            KeywordPair::new(&["while"], &["endwhile"]),
+            KeywordPair::new(&["try"], &["endtry"]),
+        ],
+        "c" | "cpp" | "objc" => vec![
+            KeywordPair::new(&["#if", "#ifdef", "#ifndef"], &["#endif"]),
+        ],
+        "pascal" | "delphi" => vec![
+            KeywordPair::new(&["begin"], &["end"]),
+        ],
+        _ => vec![],
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// Whether `token` is at byte offset b of `line` as a whole word. Word boundaries are only
+/// required on the sides where the token itself has a word char, so `#if` matches in `#if X` but
+/// not in `#ifdef X`, and `if` doesn't match inside `elseif`.
+fn token_at(line: &str, b: usize, token: &str) -> bool {
+    if !line[b..].starts_with(token) {
+        return false
+    }
+    let starts_word = token.chars().next().is_some_and(is_word_char);
+    let ends_word = token.chars().last().is_some_and(is_word_char);
+    let before = line[..b].chars().next_back();
+    let after = line[b + token.len()..].chars().next();
+    let left_ok = !starts_word || !before.is_some_and(is_word_char);
+    let right_ok = !ends_word || !after.is_some_and(is_word_char);
+    left_ok && right_ok
+}
+
+/// The keyword delimiter at byte offset b of `line`, if any, as (pair, length, is_open). The
+/// longest token wins.
+pub fn keyword_at<'p>(line: &str, b: usize, pairs: &'p [KeywordPair]) -> Option<(&'p KeywordPair, usize, bool)> {
+    pairs.iter().flat_map(|pair| {
+        let openers = pair.openers.iter().map(move |t| (pair, t, true));
+        let closers = pair.closers.iter().map(move |t| (pair, t, false));
+        openers.chain(closers)
+    })
+    .filter(|(_, token, _)| token_at(line, b, token))
+    .map(|(pair, token, open)| (pair, token.len(), open))
+    .max_by_key(|(_, len, _)| *len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::tests::{profile, Fixture};
+    use crate::utils::Pos;
+
+    #[test]
+    fn tokens_are_whole_words() {
+        assert!(token_at("#if X", 0, "#if"));
+        assert!(!token_at("#ifdef X", 0, "#if"));
+        assert!(!token_at("elseif x then", 4, "if"));
+        assert!(token_at("else if x then", 5, "if"));
+    }
+
+    #[test]
+    fn longest_token_wins() {
+        let pairs = defaults("c");
+        assert_eq!(keyword_at("#ifdef X", 0, &pairs).map(|(_, len, open)| (len, open)), Some((6, true)));
+        assert_eq!(keyword_at("#endif", 0, &pairs).map(|(_, len, open)| (len, open)), Some((6, false)));
+        assert!(keyword_at("if (x)", 0, &pairs).is_none());
+    }
+
+    #[test]
+    fn openers_share_their_nesting() {
+        // function f() if x then y() end end
+        // 1234567890123456789012345678901234
+        let fixture = Fixture::new(&["function f() if x then y() end end"], &profile(&[("filetype", "lua")]));
+        let stack = fixture.stack(Pos::new(1, 24), Pos::new(1, 24));
+        assert_eq!(stack, vec![(14, 28), (1, 32)]);
+    }
+
+}
@@ -0,0 +1,119 @@
+use crate::utils::BPairs;
+
+/// What a backslash starts in a LaTeX line.
+///
+/// Delimiter: `\begin{itemize}`, `\end{itemize}`, `\left(`, `\right\rangle`... with its pair,
+/// length in bytes and whether it opens.
+/// Escape: `\{`, `\%`, `\\`... the escaped char is text and must not be taken for a bracket.
+/// The length in bytes of the whole sequence.
+#[derive(Debug)]
+pub enum Control {
+    Delimiter(BPairs, usize, bool),
+    Escape(usize),
+}
+
+/// The control sequence at byte offset b of `line`, if it matters for pairs.
+///
+/// Environments are matched by name, so `\begin{itemize}` only closes with `\end{itemize}` and
+/// nested environments are counted right. `\left` and `\right` pair with each other whatever
+/// their delimiters are: `\left( x \right.` is a pair.
+pub fn control_at(line: &str, b: usize) -> Option<Control> {
+
+    let rest = &line[b..];
+    let command = rest.strip_prefix('\\')?;
+    let name_len = command.find(|ch: char| !ch.is_ascii_alphabetic()).unwrap_or(command.len());
+    let name = &command[..name_len];
+
+    match name {
+        "" => {
+            let escaped = command.chars().next()?;
+            Some(Control::Escape(1 + escaped.len_utf8()))
+        },
+        "begin" | "end" => {
+            let after = &rest[1 + name_len..];
+            let env = after.strip_prefix('{')?;
+            let env = &env[..env.find('}')?];
+            let len = 1 + name_len + env.len() + 2;
+            Some(Control::Delimiter(BPairs::Env(env.to_string()), len, name == "begin"))
+        },
+        "left" | "right" => {
+            let delimiter = delimiter_len(&rest[1 + name_len..])?;
+            Some(Control::Delimiter(BPairs::LeftRight, 1 + name_len + delimiter, name == "left"))
+        },
+        _ => None,
+    }
+
+}
+
+/// Length of the delimiter following `\left` or `\right`: a single char like `(` or `.`, an
+/// escaped one like `\{`, or a command like `\langle`.
+fn delimiter_len(rest: &str) -> Option<usize> {
+    let mut chars = rest.chars();
+    match chars.next()? {
+        '\\' => {
+            let command = chars.as_str();
+            match command.find(|ch: char| !ch.is_ascii_alphabetic()).unwrap_or(command.len()) {
+                0 => command.chars().next().map(|ch| 1 + ch.len_utf8()),
+                n => Some(1 + n),
+            }
+        },
+        ch if ch.is_whitespace() => None,
+        ch => Some(ch.len_utf8()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::tests::{profile, Fixture};
+    use crate::utils::Pos;
+
+    /// The pair, length and side of the delimiter at the start of `line`.
+    fn delimiter(line: &str) -> Option<(BPairs, usize, bool)> {
+        match control_at(line, 0)? {
+            Control::Delimiter(bpair, len, open) => Some((bpair, len, open)),
+            Control::Escape(_) => None,
+        }
+    }
+
+    fn escape(line: &str) -> Option<usize> {
+        match control_at(line, 0)? {
+            Control::Escape(len) => Some(len),
+            Control::Delimiter(..) => None,
+        }
+    }
+
+    #[test]
+    fn environments_by_name() {
+        let itemize = BPairs::Env(String::from("itemize"));
+        assert_eq!(delimiter("\\begin{itemize}"), Some((itemize.clone(), 15, true)));
+        assert_eq!(delimiter("\\end{itemize} x"), Some((itemize, 13, false)));
+        assert_eq!(delimiter("\\begin x"), None);
+    }
+
+    #[test]
+    fn left_and_right_whatever_their_delimiter() {
+        assert_eq!(delimiter("\\left( x"), Some((BPairs::LeftRight, 6, true)));
+        assert_eq!(delimiter("\\right\\rangle x"), Some((BPairs::LeftRight, 13, false)));
+        assert_eq!(delimiter("\\right\\} x"), Some((BPairs::LeftRight, 8, false)));
+        assert_eq!(delimiter("\\right."), Some((BPairs::LeftRight, 7, false)));
+    }
+
+    #[test]
+    fn escapes_and_other_commands() {
+        assert_eq!(escape("\\{"), Some(2));
+        assert_eq!(escape("\\%"), Some(2));
+        assert_eq!(escape("\\\\"), Some(2));
+        assert!(control_at("\\frac{a}{b}", 0).is_none());
+    }
+
+    #[test]
+    fn nested_environments_and_escaped_brackets() {
+        // \begin{a}\begin{b}\{x\end{b}\end{a}
+        // 12345678901234567890123456789012345
+        let fixture = Fixture::new(&["\\begin{a}\\begin{b}\\{x\\end{b}\\end{a}"], &profile(&[("filetype", "tex")]));
+        let stack = fixture.stack(Pos::new(1, 21), Pos::new(1, 21));
+        assert_eq!(stack, vec![(10, 22), (1, 29)]);
+    }
+
+}
@@ -0,0 +1,526 @@
+use crate::comments::Comments;
+use crate::tags::Pending;
+use crate::utils::BPairs;
+
+/// A string delimiter of a language, the same on both sides.
+///
+/// escapes: a backslash escapes the next char.
+/// multiline: the string may go on past the end of its line. Other strings have to close on the
+/// line they open, otherwise their quote is taken for plain text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub delimiter: &'static str,
+    pub escapes: bool,
+    pub multiline: bool,
+}
+
+impl Quote {
+    fn new(delimiter: &'static str, escapes: bool, multiline: bool) -> Quote {
+        Quote { delimiter, escapes, multiline }
+    }
+}
+
+/// Strings whose closing delimiter depends on how they open.
+///
+/// Rust: `r"..."`, `r#"..."#`, `br##"..."##`.
+/// Cpp: `R"(...)"`, `R"sql(...)sql"`.
+/// Lua: `[[...]]`, `[==[...]==]`, and the long comments `--[[...]]` made out of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Raw {
+    Rust,
+    Cpp,
+    Lua,
+}
+
+/// The lexical rules of a language, what tells code apart from strings and comments. The comment
+/// delimiters themselves come from the buffer options, see `Comments`.
+///
+/// quotes: string delimiters, longest first so `"""` is found before `"`.
+/// raw: strings with custom delimiters.
+/// raw_prefix: an `r` or `R` right before a quote turns escapes off, like in Python.
+/// char_literals: `'x'` and `'\n'` are chars. The `'` is never a string delimiter then.
+/// lifetimes: `'a` is a lifetime or a label, not the start of anything.
+/// regex: `/` starts a regex literal where an operand is expected, like in JavaScript.
+/// tags: markup tags are part of the code, like in JSX. The `/` of `</p>` starts no regex then.
+/// code_escapes: a backslash escapes the next char outside of strings too, so `\%` in LaTeX
+/// doesn't start a comment.
+/// apostrophes: a `'` stuck to a letter or digit on its left is an apostrophe (don't, it's) and
+/// never opens a string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Syntax {
+    pub quotes: Vec<Quote>,
+    pub raw: Option<Raw>,
+    pub raw_prefix: bool,
+    pub char_literals: bool,
+    pub lifetimes: bool,
+    pub regex: bool,
+    pub tags: bool,
+    pub code_escapes: bool,
+    pub apostrophes: bool,
+}
+
+/// Words after which a `/` starts a regex and not a division.
+const REGEX_KEYWORDS: [&str; 14] = [
+    "return", "typeof", "instanceof", "case", "do", "else", "in", "of", "new", "delete", "void",
+    "throw", "when", "unless",
+];
+
+impl Syntax {
+
+    /// The syntax of a filetype, split on dots. The first part with known rules wins, anything
+    /// else gets the default: `"`, `'` and `` ` `` strings that close on their line.
+    pub fn for_filetype(parts: &[&str]) -> Syntax {
+        parts.iter().find_map(|ft| Self::known(ft)).unwrap_or_default()
+    }
+
+    fn known(filetype: &str) -> Option<Syntax> {
+        let base = Syntax { quotes: Vec::new(), apostrophes: false, ..Syntax::default() };
+        let syntax = match filetype {
+            "rust" => Syntax {
+                quotes: vec![Quote::new("\"", true, true)],
+                raw: Some(Raw::Rust),
+                char_literals: true,
+                lifetimes: true,
+                ..base
+            },
+            "c" | "cpp" | "objc" | "objcpp" | "java" | "cs" | "arduino" => Syntax {
+                quotes: vec![Quote::new("\"", true, false)],
+                raw: if filetype == "cpp" || filetype == "objcpp" { Some(Raw::Cpp) } else { None },
+                char_literals: true,
+                ..base
+            },
+            "go" => Syntax {
+                quotes: vec![Quote::new("\"", true, false), Quote::new("`", false, true)],
+                char_literals: true,
+                ..base
+            },
+            "python" => Syntax {
+                quotes: vec![
+                    Quote::new("\"\"\"", true, true), Quote::new("'''", true, true),
+                    Quote::new("\"", true, false), Quote::new("'", true, false),
+                ],
+                raw_prefix: true,
+                ..base
+            },
+            "javascript" | "typescript" | "javascriptreact" | "typescriptreact" => Syntax {
+                quotes: vec![
+                    Quote::new("\"", true, false), Quote::new("'", true, false),
+                    Quote::new("`", true, true),
+                ],
+                regex: true,
+                apostrophes: true,
+                ..base
+            },
+            "ruby" | "perl" => Syntax {
+                quotes: vec![Quote::new("\"", true, false), Quote::new("'", true, false)],
+                regex: true,
+                ..base
+            },
+            "lua" => Syntax {
+                quotes: vec![Quote::new("\"", true, false), Quote::new("'", true, false)],
+                raw: Some(Raw::Lua),
+                ..base
+            },
+            "sh" | "bash" | "zsh" => Syntax {
+                quotes: vec![
+                    Quote::new("\"", true, false), Quote::new("'", false, false),
+                    Quote::new("`", true, false),
+                ],
+                apostrophes: true,
+                ..base
+            },
+            "lisp" | "scheme" | "clojure" | "racket" | "fennel" => Syntax {
+                quotes: vec![Quote::new("\"", true, false)],
+                ..base
+            },
+            "tex" | "plaintex" | "context" => Syntax {
+                code_escapes: true,
+                ..base
+            },
+            _ => return None,
+        };
+        Some(syntax)
+    }
+
+}
+
+impl Default for Syntax {
+    fn default() -> Syntax {
+        Syntax {
+            quotes: vec![
+                Quote::new("\"", true, false), Quote::new("'", true, false),
+                Quote::new("`", true, false),
+            ],
+            raw: None,
+            raw_prefix: false,
+            char_literals: false,
+            lifetimes: false,
+            regex: false,
+            tags: false,
+            code_escapes: false,
+            apostrophes: true,
+        }
+    }
+}
+
+/// What a span of text that is not code is.
+///
+/// String: its pair when it is delimited by a single quote char, so it can be selected as one.
+/// BlockComment: its (start, end) delimiters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+    LineComment,
+    BlockComment(String, String),
+    String(Option<BPairs>),
+    Char,
+    Regex,
+}
+
+/// Bytes [start, end) of a line that are not code, delimiters included. open_len is 0 when the
+/// span comes from a previous line and close_len is 0 when it goes on to the next one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub kind: Kind,
+    pub start: usize,
+    pub end: usize,
+    pub open_len: usize,
+    pub close_len: usize,
+}
+
+impl Span {
+    pub fn contains(&self, b: usize) -> bool {
+        self.start <= b && b < self.end
+    }
+}
+
+/// Where a line ends: in code, inside a string or block comment still waiting for `close`, or
+/// inside the attributes of a markup tag. Tags are left to `scanner::index_line`, a line starting
+/// in one is lexed from its end.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum State {
+    #[default]
+    Code,
+    Open { kind: Kind, close: String, escapes: bool },
+    Tag(Pending),
+}
+
+/// Something that starts at a byte of a line and has to be closed.
+struct Opening {
+    kind: Kind,
+    open_len: usize,
+    close: String,
+    escapes: bool,
+    multiline: bool,
+}
+
+/// Splits a line into the spans that are not code: comments, strings, chars and regexes. `state`
+/// is where the previous line ended, the returned one is where this one does.
+///
+///     let s = "a(b"; // c)
+///
+/// gives a string at bytes 8..13 and a line comment from byte 15, so neither the `(` nor the `)`
+/// are brackets.
+pub fn lex(line: &str, syntax: &Syntax, comments: &Comments, state: &State) -> (Vec<Span>, State) {
+
+    let mut spans = Vec::new();
+    let mut b = 0;
+
+    if let State::Open { kind, close, escapes } = state {
+        match find_close(line, 0, close, *escapes) {
+            Some(e) => {
+                b = e + close.len();
+                spans.push(Span { kind: kind.clone(), start: 0, end: b, open_len: 0, close_len: close.len() });
+            },
+            None => {
+                spans.push(Span { kind: kind.clone(), start: 0, end: line.len(), open_len: 0, close_len: 0 });
+                return (spans, state.clone())
+            },
+        }
+    }
+
+    // Last code char that is not blank, and where it is, to tell a regex from a division.
+    let mut last_code: Option<(usize, char)> = None;
+
+    while let Some(ch) = line[b..].chars().next() {
+
+        if syntax.code_escapes && ch == '\\' {
+            let escaped = line[b + 1..].chars().next().map_or(0, char::len_utf8);
+            last_code = Some((b, ch));
+            b += 1 + escaped;
+            continue
+        }
+
+        if ch == '\'' && syntax.char_literals {
+            if let Some(len) = char_literal_len(&line[b..]) {
+                spans.push(Span { kind: Kind::Char, start: b, end: b + len, open_len: 1, close_len: 1 });
+                last_code = Some((b, '\''));
+                b += len;
+                continue
+            }
+            if syntax.lifetimes {
+                let len = 1 + line[b + 1..].find(|c: char| !is_word_char(c)).unwrap_or(line.len() - b - 1);
+                last_code = Some((b, ch));
+                b += len;
+                continue
+            }
+        }
+
+        if let Some(opening) = opening_at(line, b, syntax, comments, last_code) {
+            if opening.kind == Kind::LineComment {
+                spans.push(Span { kind: Kind::LineComment, start: b, end: line.len(), open_len: opening.open_len, close_len: 0 });
+                return (spans, State::Code)
+            }
+            let close = if opening.kind == Kind::Regex {
+                regex_close(line, b + 1)
+            }
+            else {
+                find_close(line, b + opening.open_len, &opening.close, opening.escapes)
+            };
+            match close {
+                Some(e) => {
+                    let mut end = e + opening.close.len();
+                    if opening.kind == Kind::Regex {
+                        end += line[end..].find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(line.len() - end);
+                    }
+                    spans.push(Span {
+                        kind: opening.kind,
+                        start: b,
+                        end,
+                        open_len: opening.open_len,
+                        close_len: opening.close.len(),
+                    });
+                    last_code = Some((end - 1, '"'));
+                    b = end;
+                    continue
+                },
+                None if opening.multiline => {
+                    spans.push(Span { kind: opening.kind.clone(), start: b, end: line.len(), open_len: opening.open_len, close_len: 0 });
+                    let state = State::Open { kind: opening.kind, close: opening.close, escapes: opening.escapes };
+                    return (spans, state)
+                },
+                // A string that doesn't close is no string, its quote is plain text.
+                None => {},
+            }
+        }
+
+        if !ch.is_whitespace() {
+            last_code = Some((b, ch));
+        }
+        b += ch.len_utf8();
+    }
+
+    (spans, State::Code)
+
+}
+
+/// What opens at byte b of `line`, if anything. Comments go first, then raw strings, quotes and
+/// regexes.
+fn opening_at(line: &str, b: usize, syntax: &Syntax, comments: &Comments, last_code: Option<(usize, char)>) -> Option<Opening> {
+
+    let rest = &line[b..];
+    let before = line[..b].chars().next_back();
+
+    // Block comments and line comments, the longest delimiter wins so Lua `--[[` is not `--`.
+    let block = comments.blocks.iter()
+        .filter(|(start, _)| rest.starts_with(&start[..]))
+        .max_by_key(|(start, _)| start.len());
+    let leader = comments.leaders.iter()
+        .filter(|(l, _)| rest.starts_with(&l[..]))
+        .filter(|(l, blank)| !blank || rest[l.len()..].chars().next().is_none_or(char::is_whitespace))
+        // A leader that is also a quote, like `"` in Vim, is only a comment at the start of a line.
+        .filter(|(l, _)| !syntax.quotes.iter().any(|q| q.delimiter == &l[..]) || line[..b].trim().is_empty())
+        .map(|(l, _)| l)
+        .max_by_key(|l| l.len());
+    match (block, leader) {
+        (Some((start, end)), leader) if leader.is_none_or(|l| l.len() <= start.len()) => {
+            return Some(Opening {
+                kind: Kind::BlockComment(start.clone(), end.clone()),
+                open_len: start.len(),
+                close: end.clone(),
+                escapes: false,
+                multiline: true,
+            })
+        },
+        (_, Some(leader)) => {
+            if syntax.raw == Some(Raw::Lua) {
+                if let Some((len, close)) = lua_long_bracket(&rest[leader.len()..]) {
+                    let start = rest[..leader.len() + len].to_string();
+                    return Some(Opening {
+                        kind: Kind::BlockComment(start, close.clone()),
+                        open_len: leader.len() + len,
+                        close,
+                        escapes: false,
+                        multiline: true,
+                    })
+                }
+            }
+            return Some(Opening { kind: Kind::LineComment, open_len: leader.len(), close: String::new(), escapes: false, multiline: false })
+        },
+        _ => {},
+    }
+
+    if let Some((open_len, close)) = syntax.raw.and_then(|raw| raw_string_at(raw, line, b)) {
+        return Some(Opening { kind: Kind::String(None), open_len, close, escapes: false, multiline: true })
+    }
+
+    if let Some(quote) = syntax.quotes.iter().find(|q| rest.starts_with(q.delimiter)) {
+        let apostrophe = quote.delimiter == "'" && before.is_some_and(char::is_alphanumeric);
+        if !(syntax.apostrophes && apostrophe) {
+            let raw = syntax.raw_prefix && matches!(before, Some('r') | Some('R'));
+            let bpair = if quote.delimiter.len() == 1 { quote.delimiter.chars().next().and_then(BPairs::from_quote_char) } else { None };
+            return Some(Opening {
+                kind: Kind::String(bpair),
+                open_len: quote.delimiter.len(),
+                close: quote.delimiter.to_string(),
+                escapes: quote.escapes && !raw,
+                multiline: quote.multiline,
+            })
+        }
+    }
+
+    let closing_tag = syntax.tags && before == Some('<');
+    if syntax.regex && rest.starts_with('/') && !closing_tag && regex_allowed(line, last_code) {
+        return Some(Opening { kind: Kind::Regex, open_len: 1, close: String::from("/"), escapes: true, multiline: false })
+    }
+
+    None
+
+}
+
+/// Byte offset of `close` in `line`, searching from byte `from`. With escapes a backslash hides
+/// the char after it.
+fn find_close(line: &str, from: usize, close: &str, escapes: bool) -> Option<usize> {
+    let mut chars = line[from..].char_indices();
+    while let Some((i, ch)) = chars.next() {
+        if escapes && ch == '\\' {
+            chars.next();
+        }
+        else if line[from + i..].starts_with(close) {
+            return Some(from + i)
+        }
+    }
+    None
+}
+
+/// Byte offset of the `/` closing a regex, searching from byte `from`. A `/` inside a class like
+/// `[/(]` doesn't close it.
+fn regex_close(line: &str, from: usize) -> Option<usize> {
+    let mut chars = line[from..].char_indices();
+    let mut class = false;
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' => { chars.next(); },
+            '[' => class = true,
+            ']' => class = false,
+            '/' if !class => return Some(from + i),
+            _ => {},
+        }
+    }
+    None
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// Length of the char literal `rest` starts with: `'x'`, `'\n'`, `'\u{1F600}'`...
+fn char_literal_len(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices().skip(1);
+    let (_, first) = chars.next()?;
+    if first == '\\' {
+        // The escaped char itself may be a quote, `'\''`.
+        chars.next()?;
+        chars.take(10).find(|(_, ch)| *ch == '\'').map(|(i, _)| i + 1)
+    }
+    else if first == '\'' {
+        None
+    }
+    else {
+        chars.next().filter(|(_, ch)| *ch == '\'').map(|(i, _)| i + 1)
+    }
+}
+
+/// A `/` starts a regex at the start of a line, after an operator or an opening bracket, and
+/// after words like `return`.
+fn regex_allowed(line: &str, last_code: Option<(usize, char)>) -> bool {
+    match last_code {
+        None => true,
+        Some((b, ch)) if is_word_char(ch) => {
+            let word_start = line[..b].rfind(|c: char| !is_word_char(c)).map_or(0, |i| i + 1);
+            REGEX_KEYWORDS.contains(&&line[word_start..b + ch.len_utf8()])
+        },
+        Some((_, ch)) => "(,=:[!&|?{};+-*%<>~^".contains(ch),
+    }
+}
+
+/// The raw string opening at byte b of `line`, as (opening length, closing delimiter).
+fn raw_string_at(raw: Raw, line: &str, b: usize) -> Option<(usize, String)> {
+    let rest = &line[b..];
+    if line[..b].chars().next_back().is_some_and(is_word_char) {
+        return None
+    }
+    match raw {
+        Raw::Rust => {
+            let prefix = ["br", "cr", "r"].iter().find(|p| rest.starts_with(*p))?;
+            let hashes = rest[prefix.len()..].find(|c: char| c != '#')?;
+            rest[prefix.len() + hashes..].starts_with('"')
+                .then(|| (prefix.len() + hashes + 1, format!("\"{}", "#".repeat(hashes))))
+        },
+        Raw::Cpp => {
+            let prefix = ["u8R\"", "uR\"", "UR\"", "LR\"", "R\""].iter().find(|p| rest.starts_with(*p))?;
+            let after = &rest[prefix.len()..];
+            let paren = after.find('(').filter(|i| *i <= 16)?;
+            let delimiter = &after[..paren];
+            if delimiter.contains(|c: char| c.is_whitespace() || c == ')' || c == '\\') {
+                return None
+            }
+            Some((prefix.len() + paren + 1, format!("){}\"", delimiter)))
+        },
+        Raw::Lua => lua_long_bracket(rest),
+    }
+}
+
+/// `[[`, `[=[`, `[==[`... as (length, matching close).
+fn lua_long_bracket(rest: &str) -> Option<(usize, String)> {
+    let level = rest.strip_prefix('[')?.find(|c: char| c != '=')?;
+    rest[1 + level..].starts_with('[')
+        .then(|| (level + 2, format!("]{}]", "=".repeat(level))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The (kind, start, end) of the spans `lex` finds on a single line starting in code.
+    fn spans(line: &str, filetype: &str, comments: &str) -> Vec<(Kind, usize, usize)> {
+        let syntax = Syntax::for_filetype(&[filetype]);
+        let (spans, _) = lex(line, &syntax, &Comments::parse(comments, ""), &State::Code);
+        spans.into_iter().map(|s| (s.kind, s.start, s.end)).collect()
+    }
+
+    #[test]
+    fn strings_and_comments_are_not_code() {
+        let line = r#"let s = "a(b"; // c)"#;
+        let quote = BPairs::from_quote_char('"');
+        assert_eq!(spans(line, "rust", "s1:/*,mb:*,ex:*/,://"), vec![
+            (Kind::String(quote), 8, 13),
+            (Kind::LineComment, 15, line.len()),
+        ]);
+    }
+
+    #[test]
+    fn operators_are_no_leaders() {
+        let default = "s1:/*,mb:*,ex:*/,://,b:#,:%,:XCOMM,n:>,fb:-";
+        assert_eq!(spans("if (a > b) { x }", "", default), vec![]);
+        assert_eq!(spans("f(a % b, c)", "", default), vec![]);
+        assert_eq!(spans("a -> (b c)", "markdown", "fb:*,fb:-,fb:+,n:>"), vec![]);
+    }
+
+    #[test]
+    fn blank_leaders_need_a_blank() {
+        assert_eq!(spans("f(a, #b)", "", "b:#"), vec![]);
+        assert_eq!(spans("f(a) # b)", "", "b:#"), vec![(Kind::LineComment, 5, 9)]);
+        assert_eq!(spans("f(a) #", "", "b:#"), vec![(Kind::LineComment, 5, 6)]);
+    }
+
+}
@@ -1,361 +1,458 @@
 extern crate neovim_lib;
 
-use neovim_lib::{Neovim, NeovimApi, Session, Value, CallError};
+use neovim_lib::{Neovim, RequestHandler, Session, Value};
 
-use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
 
 mod utils;
-use utils::*;
-use utils::{Log, Logger, Pos};
-use utils::BPairs;
+use utils::{BPairs, Log, Logger, Pos};
 
 mod args;
-use args::Args;
+
+mod angle;
+mod tags;
+mod keywords;
+mod latex;
+mod comments;
+mod lexer;
+
+mod strategy;
+
+mod profile;
+use profile::Profile;
+
+mod scanner;
+use scanner::{Pair, Scanner, Target};
+
+mod mirror;
+use mirror::Mirrors;
+
+mod batch;
+use batch::Batch;
+
+mod treesitter;
+use treesitter::Node;
+
+mod backend;
+use backend::Backend;
+
+mod hints;
+
+mod command;
+use command::Command;
+
+mod edit;
+
+mod selection;
+use selection::{History, Marks, Selection};
+
+/// What `App::lookup` found out about the editor when a command came in.
+///
+/// buf: the current buffer handle, as sent by neovim.
+/// id: its buffer number.
+/// tick: its `b:changedtick`.
+/// cursor: the cursor position, with the column as 1-based byte index.
+/// profile: what to look for, from the filetype of the buffer.
+/// window: the lines around the cursor and the line number of the first one. None when the mirror
+/// of the buffer was already in sync, so nothing had to be fetched.
+/// nodes: the syntax tree nodes around the cursor, innermost first, when the filetype uses the
+/// tree-sitter backend.
+struct Lookup {
+    buf: Value,
+    id: u64,
+    tick: u64,
+    cursor: Pos,
+    profile: Profile,
+    window: Option<(Vec<String>, u64)>,
+    nodes: Vec<Node>,
+}
 
 struct App {
+    mirrors: Arc<Mutex<Mirrors>>,
+    selections: History,
+    namespace: Option<i64>,
     logger: Option<Log>
 }
 
-impl App {
+impl Lookup {
 
-    pub fn new() -> App {
-        App { logger: None }
+    /// Vimscript expression for everything a command needs to know: the current buffer, its
+    /// changedtick, the cursor, the settings of the buffer, the syntax tree nodes around the
+    /// cursor and the lines around the cursor, unless the buffer is one of `in_sync`, a vimscript
+    /// list of `[bufnr, changedtick]`.
+    fn expr(in_sync: &str) -> String {
+        format!("[bufnr('%'), b:changedtick, [line('.'), col('.') - 1], \
+                index({}, [bufnr('%'), b:changedtick]) >= 0 ? v:null : getline(max([1, line('.') - {w}]), line('.') + {w}), \
+                {}, luaeval('(function() {} end)()')]",
+            in_sync, profile::SETTINGS_EXPR, treesitter::QUERY.replace('\'', "''"), w = scanner::WINDOW)
     }
 
-    pub fn new_with_log() -> App {
-        App { logger: Some(Log::new("/tmp/delinhere_app.log")) }
-    }
-
-    fn _search_bracket_pair_backwards_arg(bracket_pair: &BPairs) -> Vec<Value> {
-        let (startb, endb) = bracket_pair.to_string_pair();
-        vec![
-            Value::from(startb),
-            Value::from(""),
-            Value::from(endb),
-            Value::from("bnW"),
-            Value::from(r#"(synIDattr(synID(line("."), col("."), 0), "name") =~? "string\\|comment")"#)
-        ]
-    }
-
-    fn _search_bracket_pair_forwards_arg(bracket_pair: &BPairs) -> Vec<Value> {
-        let (startb, endb) = bracket_pair.to_string_pair();
-        vec![
-            Value::from(startb),
-            Value::from(""),
-            Value::from(endb),
-            Value::from("nW"),
-            Value::from(r#"'(synIDattr(synID(line("."), col("."), 0), "name") =~? "string\\|comment")'"#)
-        ]
-    }
-
-    fn _unwrap_array_result_into_tuple(o_array: Value) -> Option<(u64, u64)> {
-
-        if let Some(array) = o_array.as_array() {
-            let (v1, v2) = (array[0].clone(), array[1].clone());
-            let (v1, v2) = (v1.as_u64(), v2.as_u64());
-            if let (Some(o1),Some(o2)) = (v1, v2) {
-                if o1 != 0u64 && o2 != 0u64 {
-                    Some((o1, o2))
-                }
-                else {
-                    None
-                }
-            }
-            else {
-                None
-            }
-        }
-        else {
-            None
-        }
-    }
+    /// Decode the value of `expr`.
+    fn new(value: &Value) -> Option<Lookup> {
 
-    fn searchpairpos(nvim: &mut Neovim, args: Vec<Value>) -> Option<Pos> {
-
-        let search = nvim.call_function("searchpairpos", args);
-        match search {
-            Ok(array) => {
-                if let Some((line, col)) = Self::_unwrap_array_result_into_tuple(array) {
-                    Some(Pos::new(line,col))
-                }
-                else {
-                    None
-                }
-            },
-            //TODO: We discard call errors for now. We should handle them
-            Err(_error) => {
-                None
-            },
+        let results = value.as_array()?;
+        if results.len() < 6 {
+            return None
         }
+        let buf = results[0].clone();
+        let id = mirror::buffer_id(&buf)?;
+        let tick = results[1].as_u64()?;
+        let cursor = results[2].as_array()?;
+        let cursor = Pos::new(cursor.first()?.as_u64()?, cursor.get(1)?.as_u64()? + 1);
+        let window = results[3].as_array().map(|array| {
+            let lines = array.iter().map(|v| v.as_str().unwrap_or("").to_string()).collect();
+            (lines, cursor.line().saturating_sub(scanner::WINDOW).max(1))
+        });
+        let profile = Profile::new(&results[4]);
+        let nodes = Node::stack(&results[5]);
 
-    }
+        Some(Lookup { buf, id, tick, cursor, profile, window, nodes })
 
-    fn find_closest_bpair(&mut self, nvim: &mut Neovim) -> Option<(BPairs, Pos)> {
+    }
 
-        let mut dual: Option<(BPairs, Pos)> = None;
+    /// Run `f` with a scanner over the buffer: its mirror when it is in sync, otherwise the lines
+    /// that were fetched. The mirrors stay locked until `f` returns, so it must not wait on neovim,
+    /// see `Requests`.
+    fn with_scanner<T, F>(&self, mirrors: &Mutex<Mirrors>, f: F) -> Option<T>
+    where F: FnOnce(&Scanner) -> Option<T> {
 
-        for bpair in BPairs::array().iter() {
-            let args: Vec<Value> = App::_search_bracket_pair_backwards_arg(bpair);
-            // TODO: Here we never check if the closest is a valid bracket, we could potentially
-            // find unbalanced bpairs.
-            let search = App::searchpairpos(nvim, args);
-            if let Some(pos) = search {
-                dual = if let Some((old_bpair, old_pos)) = dual {
-                    if pos > old_pos {
-                        Some((bpair.clone(), pos))
-                    }
-                    else {
-                        Some((old_bpair, old_pos))
-                    }
-                }
-                else {
-                    Some((bpair.clone(), pos))
-                }
-            }
+        if let Some(mirror) = mirrors.lock().unwrap().get(self.id, self.tick, &self.profile) {
+            return f(&mirror.scanner())
         }
 
-        self.log(&format!("Closest {:?}\n", dual));
-        dual
+        let (lines, first_line) = self.window.as_ref()?;
+        let index = scanner::index_lines(lines, &self.profile);
+        f(&Scanner::new(lines, &index, *first_line))
 
     }
 
-    fn find_args(&mut self, nvim: &mut Neovim) -> Option<args::Args> {
-
-        if let Some((bpair, bpos)) = self.find_closest_bpair(nvim) {
-            let searchpairpos_args: Vec<Value> = App::_search_bracket_pair_forwards_arg(&bpair);
-            let search = App::searchpairpos(nvim, searchpairpos_args);
-            self.log(&format!("search {:?}\n", search));
+}
 
-            if let Some(epos) = search {
-                let (line, col) = epos.get();
-                self.log(&format!("line {} col {}\n", line, col));
-                self.log(&format!("from {} to {}\n", bpos.line(), line));
-                let getline_args = vec![Value::from(bpos.line()), Value::from(line)];
-                let lines = nvim.call_function("getline", getline_args);
-                let mut args = Args::new(lines.unwrap(), bpos, epos, &mut self.logger);
-                self.log(&format!("all {:?}\n", args));
-                Some(args)
-            } else { None }
-        } else { None }
+impl App {
 
+    pub fn new() -> App {
+        App { mirrors: Arc::new(Mutex::new(Mirrors::new())), selections: History::new(), namespace: None, logger: None }
     }
 
-    fn test(&mut self, nvim: &mut Neovim, values: Vec<Value>) {
-
-        let args = self.find_args(nvim);
-        self.log("After find_args\n");
-        let string = args.map_or(String::new(), |mut a|{
-            self.log("Before fuckup\n");
-            a.reconstruct_args()
-        });
-        // let string = String::from("hola");
-
-        self.log(&format!("reconstruct := {:?}\n", &string));
-
-        if let Some((bpair, _pos)) = self.find_closest_bpair(nvim) {
-
-            let cmd = Self::_verb_adverb_here("d", "i", &bpair.to_simple_string_open());
-            let dihargs = Value::from(vec!(
-                    Value::from(cmd),
-                    Value::from("n"),
-                    Value::from(false)
-            ));
-
-            let call_dih = Value::from(vec![
-                                       Value::from("nvim_feedkeys"), dihargs
-            ]);
-
-            let setregargs = Value::from(vec!(
-                Value::from("-"),
-                Value::from(string),
-            ));
-
-            let call_wrapper = Value::from(vec![Value::from("setreg"), setregargs]);
-
-            let call_setreg = Value::from(vec!(
-                    Value::from("nvim_call_function"), call_wrapper
-
-            ));
-
-            let feedargs_2 = Value::from(vec!(
-                    Value::from("\"-P"),
-                    Value::from("n"),
-                    Value::from(false)
-            ));
+    pub fn new_with_log() -> App {
+        App { mirrors: Arc::new(Mutex::new(Mirrors::new())), selections: History::new(), namespace: None, logger: Some(Log::new("/tmp/delinhere_app.log")) }
+    }
 
+    /// Everything a command needs to know, fetched with a single `nvim_eval` of `Lookup::expr`.
+    /// The lines come along unless the mirror of the buffer is already in sync.
+    fn lookup(&mut self, nvim: &mut Neovim) -> Option<Lookup> {
 
-            let call_write = Value::from(vec!(
-                    Value::from("nvim_feedkeys"), feedargs_2
-            ));
+        let mut batch = Batch::new();
+        let in_sync = self.mirrors.lock().unwrap().in_sync_list();
+        batch.eval(&Lookup::expr(&in_sync));
 
-            let atomic_call = vec![call_dih, call_setreg, call_write];
+        let results = match batch.send(nvim) {
+            Ok(results) => results,
+            Err(err) => {
+                self.log_err("lookup ", err);
+                return None
+            }
+        };
+        let lookup = Lookup::new(results.first()?);
+        self.log(&format!("lookup := {:?}\n", lookup.as_ref().map(|l| (l.id, l.tick, l.cursor, &l.profile))));
+        lookup
 
-            self.log(&format!("atomic_call := {:?}\n", &atomic_call));
+    }
 
-            let result = nvim.call_atomic(atomic_call);
+    /// Send the edits of a command as a single `nvim_call_atomic`. Buffers that are not mirrored
+    /// yet get attached in the same request.
+    fn apply(&mut self, nvim: &mut Neovim, lookup: &Lookup, mut batch: Batch) {
 
-            self.log(&format!("result := {:?}\n", &result));
+        let attach = !self.mirrors.lock().unwrap().is_attached(lookup.id);
+        if attach {
+            batch.push("nvim_buf_attach", vec![lookup.buf.clone(), Value::from(true), Value::Map(vec![])]);
+        }
+        if batch.is_empty() {
+            return
+        }
 
+        match batch.send(nvim) {
+            Ok(results) => {
+                if attach && results.last().and_then(Value::as_bool) == Some(true) {
+                    self.mirrors.lock().unwrap().attach(lookup.id);
+                }
+            },
+            Err(err) => self.log_err("apply ", err),
         }
 
     }
 
-    fn test_3(&mut self, nvim: &mut Neovim, values: Vec<Value>) {
-
-        let string = String::from("This is a test string");
-
-        if let Some((bpair, _pos)) = self.find_closest_bpair(nvim) {
-
-            let cmd = Self::_verb_adverb_here("d", "i", &bpair.to_simple_string_open());
-            let dihargs = Value::from(vec!(
-                    Value::from(cmd),
-                    Value::from("n"),
-                    Value::from(false)
-            ));
-
-            let call_dih = Value::from(vec![
-                                       Value::from("nvim_feedkeys"), dihargs
-            ]);
-
-            let setregargs = Value::from(vec!(
-                Value::from("\"j"),
-                Value::from(string),
-            ));
-
-            let call_wrapper = Value::from(vec![Value::from("setreg"), setregargs]);
-
-            let call_setreg = Value::from(vec!(
-                    Value::from("nvim_call_function"), call_wrapper
+    /// Run `f` with a scanner over the current buffer: the mirror when it is in sync, otherwise
+    /// the lines fetched by `lookup`.
+    fn with_scanner<T, F>(&mut self, lookup: &Lookup, f: F) -> Option<T>
+    where F: FnOnce(&Scanner, Pos, &mut Option<Log>) -> Option<T> {
 
-            ));
-
-            let feedargs_2 = Value::from(vec!(
-                    Value::from("\"jP"),
-                    Value::from("n"),
-                    Value::from(false)
-            ));
+        if lookup.window.is_some() {
+            self.log(&format!("Buffer {} not mirrored at tick {}\n", lookup.id, lookup.tick));
+        }
+        let logger = &mut self.logger;
+        lookup.with_scanner(&self.mirrors, |scanner| f(scanner, lookup.cursor, logger))
 
+    }
 
-            let call_write = Value::from(vec!(
-                    Value::from("nvim_feedkeys"), feedargs_2
-            ));
+    /// Run `f` with the backend of the current buffer: the syntax tree nodes of the lookup if
+    /// there are any and their lines were fetched, the text otherwise.
+    fn with_backend<T, F>(&mut self, lookup: &Lookup, f: F) -> Option<T>
+    where F: FnOnce(&dyn Backend, &Scanner, Pos, &mut Option<Log>) -> Option<T> {
 
-            let atomic_call = vec![call_setreg, call_dih, call_write];
+        self.with_scanner(lookup, |scanner, cursor, logger| Self::on_backend(lookup, scanner, cursor, logger, f))
 
-            self.log(&format!("atomic_call := {:?}\n", &atomic_call));
+    }
 
-            let result = nvim.call_atomic(atomic_call);
+    /// Run `f` with the backend of the buffer `scanner` goes over, see `with_backend`.
+    fn on_backend<T, F>(lookup: &Lookup, scanner: &Scanner, cursor: Pos, logger: &mut Option<Log>, f: F) -> Option<T>
+    where F: FnOnce(&dyn Backend, &Scanner, Pos, &mut Option<Log>) -> Option<T> {
 
-            self.log(&format!("result := {:?}\n", &result));
+        let mut nesting: Vec<BPairs> = BPairs::array().to_vec();
+        nesting.extend(lookup.profile.pairs.iter().map(|(open, close)| BPairs::Custom(open.clone(), close.clone())));
 
+        let nodes: Vec<Node> = lookup.nodes.iter()
+            .filter(|node| scanner.has_lines(node.pair.open.line(), node.pair.close.line()))
+            .cloned()
+            .collect();
+        if nodes.is_empty() {
+            f(&backend::Text { scanner, nesting: &nesting }, scanner, cursor, logger)
         }
-
-    }
-
-    fn test_2(&mut self, nvim: &mut Neovim, values: Vec<Value>) {
-        self.log("Inside test method\n");
-
-        let args = self.find_args(nvim);
-        if let Some(mut a) = args {
-            let arg_string = a.reconstruct_args();
-            self.delete_in_here(nvim);
-            self.write_in_here(nvim, arg_string);
+        else {
+            f(&backend::TreeSitter { scanner, nodes: &nodes }, scanner, cursor, logger)
         }
 
     }
 
-    fn _test(&mut self, values: Vec<Value>) -> String {
-        let map = values[0].as_map().unwrap();
-        let mut hashmap: HashMap<String, Value> = HashMap::new();
-        for (key, val) in map {
-            if let Some(key_string) = key.as_str() {
-                self.log(&format!("key_string {:?} -> val {:?}\n", key_string, val));
-                hashmap.insert(key_string.to_string(), val.clone());
-            }
-        }
-        return "dummy".to_string()
-    }
+    /// The namespace of the extmarks of the plugin, created the first time it is needed.
+    fn namespace(&mut self, nvim: &mut Neovim) -> Option<i64> {
 
-    fn _dih_write(&mut self, nvim: &mut Neovim, verb: &str, adverb: &str) {
-        if let Some((bpair, _pos)) = self.find_closest_bpair(nvim) {
-            let cmd = Self::_verb_adverb_here(verb, adverb, &bpair.to_simple_string_open());
-            let args = vec![Value::from(cmd),
-            Value::from("n")];
-            if let Err(err) = nvim.call_function("feedkeys", args) {
-                self.log_err("call_dih_w_feedkeys ", err)
+        if self.namespace.is_none() {
+            let mut batch = Batch::new();
+            batch.push("nvim_create_namespace", vec![Value::from("delinhere")]);
+            match batch.send(nvim) {
+                Ok(results) => self.namespace = results.first().and_then(Value::as_i64),
+                Err(err) => self.log_err("namespace ", err),
             }
         }
+        self.namespace
+
     }
 
-    fn write_in_here(&mut self, nvim: &mut Neovim, string: String) {
+    /// Rewrite the arguments of the pair around the cursor. The old ones go to the register of
+    /// `command`, like with `c`. Like deletes, this stores `command` in `g:delinhere_repeat` and
+    /// goes through an operator, `<Plug>(DelinhereTest)`, so `.` rewrites the arguments at the new
+    /// cursor. The edit is found here and handed over in `g:delinhere_answer`, see `queue_verb`.
+    fn test(&mut self, nvim: &mut Neovim, command: &Command) {
 
-        let setregargs = vec![
-            Value::from("\"\""),
-            Value::from(string),
-            Value::from("n")
-        ];
+        let lookup = match self.lookup(nvim) {
+            Some(lookup) => lookup,
+            None => return,
+        };
+        let answer = self.with_backend(&lookup, |backend, scanner, cursor, logger| {
+            Some(Requests::test(backend, scanner, cursor, command, logger))
+        });
 
-        if let Err(err) = nvim.call_function("setreg", setregargs) {
-            self.log_err("setreg ", err)
+        let mut batch = Batch::new();
+        let answer = answer.unwrap_or_else(|| Value::Array(Vec::new()));
+        Self::queue_answer(&mut batch, lookup.id, lookup.tick, answer);
+        batch.push("nvim_set_var", vec![Value::from("delinhere_repeat"), command.to_value()]);
+        batch.eval("feedkeys(\"\\<Plug>(DelinhereTest)\", 'm')");
+
+        self.log(&format!("batch := {:?}\n", &batch));
+        self.apply(nvim, &lookup, batch);
+
+    }
+
+    /// The keys of `verb` using `register`, as in `v:register`. The default register when empty.
+    fn with_register(register: &str, verb: &str) -> String {
+        if register.is_empty() { verb.to_string() } else { format!("\"{}{}", register, verb) }
+    }
+
+    /// Queue the verb of `command` on the inside or around of `pair`, as its adverb says. This
+    /// stores `command` in `g:delinhere_repeat` and feeds a mapping that takes it from there:
+    ///
+    /// - "v" gets `<Plug>(DelinhereSelect)`, which selects the text object.
+    /// - "d" gets `<Plug>(DelinhereEdit)`, an operator whose edits are made through the buffer
+    ///   API, see `edit::queue`.
+    /// - "y" gets `<Plug>(DelinhereYank)`, which makes the same edits but no operator, since `.`
+    ///   doesn't repeat yanks.
+    /// - any other operator is fed with `<Plug>(DelinhereRepeat)` as its text object.
+    ///
+    /// The mapping finds the answer to its request on `pair` in `g:delinhere_answer`, along with
+    /// the buffer `id` and changedtick `tick` it holds for, so this time nothing is looked up again. `.` has no
+    /// answer and asks `Requests`, which looks up the pair `command` asks for at the new cursor.
+    /// `command` must then pick it by its count alone, hints being over.
+    fn queue_verb(batch: &mut Batch, scanner: &Scanner, pair: &Pair, command: &Command, id: u64, tick: u64) {
+
+        batch.push("nvim_set_var", vec![Value::from("delinhere_repeat"), command.to_value()]);
+        if command.adverb == "i" && command.verb == "c" && scanner.inside(pair).is_none() {
+            // Nothing inside, changing it is inserting right before the closing delimiter.
+            let cursor = vec![Value::from(pair.close.line()), Value::from(pair.close.col() - 1)];
+            batch.push("nvim_win_set_cursor", vec![Value::from(0), Value::from(cursor)]);
+            batch.call_function("feedkeys", vec![Value::from("i"), Value::from("n")]);
+            return
         }
 
-        let feedargs = vec![
-            Value::from("\"\"P"),
-            Value::from("n")
-        ];
-
-        if let Err(err) = nvim.call_function("feedkeys", feedargs) {
-            self.log_err("feedkeys ", err)
+        let answer = match command.verb.as_str() {
+            "d" | "y" => Requests::edit(scanner, pair, command),
+            _ => Requests::text_object(scanner, pair, command),
+        };
+        Self::queue_answer(batch, id, tick, answer);
+        match command.verb.as_str() {
+            "v" => batch.eval("feedkeys(\"\\<Plug>(DelinhereSelect)\", 'm')"),
+            "d" => batch.eval("feedkeys(\"\\<Plug>(DelinhereEdit)\", 'm')"),
+            "y" => batch.eval("feedkeys(\"\\<Plug>(DelinhereYank)\", 'm')"),
+            verb => {
+                batch.feedkeys(&Self::with_register(&command.register, verb), "n");
+                batch.eval("feedkeys(\"\\<Plug>(DelinhereRepeat)\", 'm')");
+            },
         }
 
-
     }
 
-    fn _verb_adverb_here(verb: &str, adverb: &str, here: &str) -> String {
-        format!("{}{}{}", verb, adverb, here)
+    /// Hand `answer` over to the mapping that asks for it, in `g:delinhere_answer`. It only holds
+    /// for buffer `id` at changedtick `tick`.
+    fn queue_answer(batch: &mut Batch, id: u64, tick: u64, answer: Value) {
+        let answer = vec![Value::from(id), Value::from(tick), answer];
+        batch.push("nvim_set_var", vec![Value::from("delinhere_answer"), Value::from(answer)]);
+    }
+
+    /// Queue selecting `selection`, in its mode, through the `'<` and `'>` marks. `exclusive` is
+    /// whether 'selection' is, see `Marks`.
+    fn queue_selection(batch: &mut Batch, scanner: &Scanner, selection: &Selection, exclusive: bool) {
+        let mark = |pos: Pos| Value::from(vec![
+            Value::from(0), Value::from(pos.line()), Value::from(pos.col()), Value::from(0)
+        ]);
+        let (start, end) = Marks::of(scanner, selection, exclusive);
+        batch.call_function("setpos", vec![Value::from("'<"), mark(start)]);
+        batch.call_function("setpos", vec![Value::from("'>"), mark(end)]);
+        batch.call_function("feedkeys", vec![Value::from(format!("`<{}`>", selection.mode)), Value::from("n")]);
+    }
+
+    /// The pairs `target` designates from the cursor, in the order a count goes through them. The
+    /// pairs around the cursor are ordered by the strategy of the profile. The next and last ones
+    /// are only found as far as they are taken.
+    fn targets<'s>(backend: &dyn Backend, scanner: &'s Scanner, cursor: Pos, target: Target, profile: &Profile)
+        -> Box<dyn Iterator<Item = Pair> + 's> {
+        match target {
+            Target::Here => {
+                let stack = backend.stack(cursor, cursor);
+                let on_line = stack.first()
+                    .is_some_and(|p| p.open.line() == cursor.line() || p.close.line() == cursor.line());
+                // Seeking only comes first when the pairs around are away from the cursor line.
+                let seek = if on_line { None } else { scanner.seek(cursor) };
+                Box::new(seek.into_iter().chain(profile.strategy.order(stack, scanner, cursor)))
+            },
+            Target::Next => Box::new(scanner.next_pairs(cursor)),
+            Target::Last => Box::new(scanner.last_pairs(cursor)),
+        }
     }
 
-    fn call_dih_w_feedkeys(&mut self, nvim: &mut Neovim, verb: &str, adverb: &str) {
-        if let Some((bpair, _pos)) = self.find_closest_bpair(nvim) {
-            let cmd = Self::_verb_adverb_here(verb, adverb, &bpair.to_simple_string_open());
-            let args = vec![Value::from(cmd), Value::from("n")];
-            if let Err(err) = nvim.call_function("feedkeys", args) {
-                self.log_err("call_dih_w_feedkeys ", err)
+    /// Act with the verb of `command` on the pair it asks for: the one its count picks among the
+    /// pairs its target designates, the nearest one being the first.
+    fn call_dih_w_feedkeys(&mut self, nvim: &mut Neovim, command: &Command) {
+        let lookup = match self.lookup(nvim) {
+            Some(lookup) => lookup,
+            None => return,
+        };
+        // Without a count, the hints pick among the pairs around the cursor. They wait for a key,
+        // so the pairs are found first and the mirrors are not locked meanwhile.
+        let mut command = command.clone();
+        let hints = lookup.profile.hints && command.count <= 1 && command.target == Target::Here;
+        let namespace = if hints { self.namespace(nvim) } else { None };
+        if let Some(namespace) = namespace {
+            let targets: Vec<Pair> = self.with_backend(&lookup, |backend, scanner, cursor, _| {
+                Some(Self::targets(backend, scanner, cursor, command.target, &lookup.profile)
+                    .filter(|pair| command.accepts(pair))
+                    .collect())
+            }).unwrap_or_default();
+            if targets.len() > 1 {
+                match hints::pick(nvim, namespace, &targets) {
+                    Ok(Some(index)) => command.count = index as u64 + 1,
+                    Ok(None) => return,
+                    Err(err) => {
+                        self.log_err("hints ", err);
+                        return
+                    },
+                }
             }
         }
-    }
-
-    fn delete_in_here(&mut self, nvim: &mut Neovim) {
-        self.call_dih_w_feedkeys(nvim, "d", "i");
-    }
-
-    fn delete_around_here(&mut self, nvim: &mut Neovim) {
-        self.call_dih_w_feedkeys(nvim, "d", "a");
-    }
+        let mut batch = Batch::new();
+        let pair = self.with_backend(&lookup, |backend, scanner, cursor, _| {
+            let pair = command.pick(Self::targets(backend, scanner, cursor, command.target, &lookup.profile))?;
+            Self::queue_verb(&mut batch, scanner, &pair, &command, lookup.id, lookup.tick);
+            Some(pair)
+        });
+        self.log(&format!("{:?} on {:?}\n", command, pair));
+        self.apply(nvim, &lookup, batch);
+    }
+
+    /// Grow the last Visual selection of `window`, as given by its `marks`, to the smallest pair
+    /// holding all of it. With `adverb` "a" that's its inside first when it's larger than the
+    /// selection, then the pair, then the inside of the next one and so on. With "i" it's only
+    /// insides. The pairs are selected charwise. Each step is remembered for `shrink_selection`.
+    fn grow_selection(&mut self, nvim: &mut Neovim, window: u64, marks: &Marks, adverb: &str) {
+
+        let lookup = match self.lookup(nvim) {
+            Some(lookup) => lookup,
+            None => return,
+        };
+        let mut batch = Batch::new();
+        let grown = self.with_backend(&lookup, |backend, scanner, _, _| {
+            if !scanner.has_lines(marks.range.0.line(), marks.range.1.line()) {
+                return None
+            }
+            let selection = marks.selection(scanner);
+            let (first, last) = selection.range;
+            let grown = backend.stack(first, last).iter()
+                .flat_map(|pair| {
+                    let around = if adverb == "a" { Some(scanner.around(pair)) } else { None };
+                    scanner.inside(pair).into_iter().chain(around)
+                })
+                .find(|&(start, end)| start <= first && last <= end && (start, end) != selection.range)?;
+            let grown = Selection { range: grown, mode: String::from("v") };
+            Self::queue_selection(&mut batch, scanner, &grown, marks.exclusive);
+            Some((selection, grown))
+        });
+        self.log(&format!("Grown {:?}\n", grown));
 
-    fn change_in_here(&mut self, nvim: &mut Neovim) {
-        self.call_dih_w_feedkeys(nvim, "c", "i");
-    }
+        match grown {
+            Some((selection, grown)) => self.selections.grow(window, selection, grown),
+            // Nothing to grow to, the selection stays.
+            None => batch.feedkeys("gv", "n"),
+        }
+        self.apply(nvim, &lookup, batch);
 
-    fn change_around_here(&mut self, nvim: &mut Neovim) {
-        self.call_dih_w_feedkeys(nvim, "c", "a");
     }
 
-    fn select_in_here(&mut self, nvim: &mut Neovim) {
-        self.call_dih_w_feedkeys(nvim, "v", "i");
-    }
+    /// Go back to the selection `grow_selection` grew the last Visual selection of `window` from,
+    /// in the mode it had. A selection that wasn't grown stays as it is.
+    fn shrink_selection(&mut self, nvim: &mut Neovim, window: u64, marks: &Marks) {
 
-    fn select_around_here(&mut self, nvim: &mut Neovim) {
-        self.call_dih_w_feedkeys(nvim, "v", "a");
-    }
+        let lookup = match self.lookup(nvim) {
+            Some(lookup) => lookup,
+            None => return,
+        };
+        let mut batch = Batch::new();
+        let selections = &mut self.selections;
+        let shrunk = lookup.with_scanner(&self.mirrors, |scanner| {
+            if !scanner.has_lines(marks.range.0.line(), marks.range.1.line()) {
+                return None
+            }
+            let shrunk = selections.shrink(window, &marks.selection(scanner))
+                .filter(|shrunk| scanner.has_lines(shrunk.range.0.line(), shrunk.range.1.line()))?;
+            Self::queue_selection(&mut batch, scanner, &shrunk, marks.exclusive);
+            Some(shrunk)
+        });
+        self.log(&format!("Shrunk {:?}\n", shrunk));
 
-    fn yank_in_here(&mut self, nvim: &mut Neovim) {
-        self.call_dih_w_feedkeys(nvim, "y", "i");
-    }
+        if shrunk.is_none() {
+            batch.feedkeys("gv", "n");
+        }
+        self.apply(nvim, &lookup, batch);
 
-    fn yank_around_here(&mut self, nvim: &mut Neovim) {
-        self.call_dih_w_feedkeys(nvim, "y", "a");
     }
 
 }
@@ -377,35 +474,127 @@ impl Logger for App {
 }
 
 enum Messages {
-    DelInHere,
-    DelArHere,
-    ChaInHere,
-    ChaArHere,
-    SelInHere,
-    SelArHere,
-    YanInHere,
-    YanArHere,
+    Here,
+    GrowHere,
+    ShrinkHere,
     Test,
+    BufLines,
+    BufChangedtick,
+    BufDetach,
     Unknown(String),
 }
 
 impl From<String> for Messages {
     fn from(event: String) -> Self {
         match &event[..] {
-            "DelInHere" => Messages::DelInHere,
-            "DelArHere" => Messages::DelArHere,
-            "ChaInHere" => Messages::ChaInHere,
-            "ChaArHere" => Messages::ChaArHere,
-            "SelInHere" => Messages::SelInHere,
-            "SelArHere" => Messages::SelArHere,
-            "YanInHere" => Messages::YanInHere,
-            "YanArHere" => Messages::YanArHere,
+            "Here" => Messages::Here,
+            "GrowHere" => Messages::GrowHere,
+            "ShrinkHere" => Messages::ShrinkHere,
             "Test" => Messages::Test,
+            "nvim_buf_lines_event" => Messages::BufLines,
+            "nvim_buf_changedtick_event" => Messages::BufChangedtick,
+            "nvim_buf_detach_event" => Messages::BufDetach,
             _ => Messages::Unknown(event),
         }
     }
 }
 
+/// Answers the requests neovim waits on: the text objects, `ih`, `ah` and the one operators are
+/// fed with, which must have made their selection by the time their mapping is done, and the edits
+/// of `<Plug>(DelinhereEdit)` and `<Plug>(DelinhereTest)`. The fed ones only ask when `.` repeats them, see `App::queue_verb`.
+/// Requests are handled right in the thread reading from neovim, and that thread would have to
+/// read the answer to any call back into neovim. So a request brings the value of `Lookup::expr`
+/// and is answered from it and the mirrors, which it shares with `App`. `App` never keeps them
+/// locked while it waits on neovim, or the answer it waits for would never be read.
+struct Requests {
+    mirrors: Arc<Mutex<Mirrors>>,
+}
+
+impl RequestHandler for Requests {
+    fn handle_request(&mut self, name: &str, args: Vec<Value>) -> Result<Value, Value> {
+        match name {
+            // Asked before every request, the mirrors in sync change with every edit.
+            "LookupExpr" => Ok(Value::from(Lookup::expr(&self.mirrors.lock().unwrap().in_sync_list()))),
+            "TextObject" => Ok(self.with_pair(&args, Self::text_object).unwrap_or(Value::Nil)),
+            "Edit" => Ok(self.with_pair(&args, Self::edit).unwrap_or_else(|| Value::Array(Vec::new()))),
+            "Test" => Ok(self.with_backend(&args, |backend, scanner, cursor, command, _| {
+                Some(Self::test(backend, scanner, cursor, command, &mut None))
+            }).unwrap_or_else(|| Value::Array(Vec::new()))),
+            _ => Err(Value::from(format!("Unknown request {}", name))),
+        }
+    }
+}
+
+impl Requests {
+
+    /// The first and last char of the text object of `command` on `pair`, and the Visual mode to
+    /// select it with, as `[[line, col], [line, col], mode]`, see `edit::object`. The verb doesn't
+    /// matter. Nil when there is nothing inside the pair.
+    fn text_object(scanner: &Scanner, pair: &Pair, command: &Command) -> Value {
+        edit::object(scanner, pair, &command.adverb).map_or(Value::Nil, |(start, end, mode)| {
+            let pos = |pos: Pos| Value::from(vec![Value::from(pos.line()), Value::from(pos.col())]);
+            Value::from(vec![pos(start), pos(end), Value::from(mode)])
+        })
+    }
+
+    /// The calls that make the edit of `command`, "d" or "y", on `pair`, for vimscript to make
+    /// them.
+    fn edit(scanner: &Scanner, pair: &Pair, command: &Command) -> Value {
+        let mut batch = Batch::new();
+        if let Some(object) = edit::object(scanner, pair, &command.adverb) {
+            edit::queue(&mut batch, scanner, object, &command.verb, &command.register);
+        }
+        Value::from(batch.into_calls())
+    }
+
+    /// The calls that rewrite the arguments of the innermost pair around `cursor`, the old ones
+    /// going to the register of `command`, for vimscript to make them.
+    fn test(backend: &dyn Backend, scanner: &Scanner, cursor: Pos, command: &Command, logger: &mut Option<Log>) -> Value {
+
+        let mut batch = Batch::new();
+        if let Some(pair) = backend.stack(cursor, cursor).into_iter().next() {
+            if let Some(logger) = logger { logger.log(&format!("from {:?} to {:?}\n", pair.open, pair.close)); }
+            let mut args = backend.args(&pair, logger);
+            if let Some(logger) = logger { logger.log(&format!("all {:?}\n", args)); }
+
+            let string = args.reconstruct_args();
+            if let Some(logger) = logger { logger.log(&format!("reconstruct := {:?}\n", string)); }
+            if let Some(object) = edit::object(scanner, &pair, "i") {
+                edit::queue_replace(&mut batch, scanner, object, &string, &command.register);
+            }
+        }
+        Value::from(batch.into_calls())
+
+    }
+
+    /// Answer with `f` on the pair the command picks. args are the lookup and the command.
+    fn with_pair<F>(&self, args: &[Value], f: F) -> Option<Value>
+    where F: FnOnce(&Scanner, &Pair, &Command) -> Value {
+
+        self.with_backend(args, |backend, scanner, cursor, command, lookup| {
+            let pair = command.pick(App::targets(backend, scanner, cursor, command.target, &lookup.profile))?;
+            Some(f(scanner, &pair, command))
+        })
+
+    }
+
+    /// Answer with `f` on the backend of the buffer, from the lookup in args. args are the lookup
+    /// and the command.
+    fn with_backend<F>(&self, args: &[Value], f: F) -> Option<Value>
+    where F: FnOnce(&dyn Backend, &Scanner, Pos, &Command, &Lookup) -> Option<Value> {
+
+        let lookup = Lookup::new(args.first()?)?;
+        let command = Command::new(args.get(1)?)?;
+        lookup.with_scanner(&self.mirrors, |scanner| {
+            App::on_backend(&lookup, scanner, lookup.cursor, &mut None, |backend, scanner, cursor, _| {
+                f(backend, scanner, cursor, &command, &lookup)
+            })
+        })
+
+    }
+
+}
+
 struct EventHandler<'a> {
     nvim: Neovim,
     app: App,
@@ -438,24 +627,47 @@ impl<'a> EventHandler<'a> {
         self.log(&format!("Processing message from event type {}\n", event));
         let message = Messages::from(event);
         match message {
-            Messages::DelInHere => { self.app.delete_in_here(&mut self.nvim); },
-            Messages::DelArHere => { self.app.delete_around_here(&mut self.nvim); },
-            Messages::ChaInHere => { self.app.change_in_here(&mut self.nvim); },
-            Messages::ChaArHere => { self.app.change_around_here(&mut self.nvim); },
-            Messages::SelInHere => { self.app.select_in_here(&mut self.nvim); },
-            Messages::SelArHere => { self.app.select_around_here(&mut self.nvim); },
-            Messages::YanInHere => { self.app.yank_in_here(&mut self.nvim); },
-            Messages::YanArHere => { self.app.yank_around_here(&mut self.nvim); },
+            Messages::Here => match values.first().and_then(Command::new).filter(|c| !c.verb.is_empty()) {
+                Some(command) => { self.app.call_dih_w_feedkeys(&mut self.nvim, &command); },
+                None => { self.log(&format!("No verb in {:?}\n", values)); },
+            },
+            Messages::GrowHere | Messages::ShrinkHere => {
+                // These get the window, the marks of the selection, its mode and 'selection'
+                // instead, and growing gets "i" or "a".
+                let window = values.first().and_then(Value::as_u64).unwrap_or(0);
+                let mark = |value: Option<&Value>| -> Option<Pos> {
+                    let mark = value?.as_array()?;
+                    Some(Pos::new(mark.first()?.as_u64()?, mark.get(1)?.as_u64()?))
+                };
+                if let (Some(start), Some(end)) = (mark(values.get(1)), mark(values.get(2))) {
+                    let mode = values.get(3).and_then(Value::as_str).filter(|mode| !mode.is_empty()).unwrap_or("v");
+                    let exclusive = values.get(4).and_then(Value::as_str) == Some("exclusive");
+                    let marks = Marks { range: (start, end), mode: mode.to_string(), exclusive };
+                    if let Messages::GrowHere = message {
+                        let adverb = values.get(5).and_then(Value::as_str).unwrap_or("a");
+                        self.app.grow_selection(&mut self.nvim, window, &marks, adverb);
+                    }
+                    else {
+                        self.app.shrink_selection(&mut self.nvim, window, &marks);
+                    }
+                }
+            },
             Messages::Test => {
                 self.log("Inside Test branch\n");
-                self.app.test(&mut self.nvim, values);
+                match values.first().and_then(Command::new) {
+                    Some(command) => { self.app.test(&mut self.nvim, &command); },
+                    None => { self.log(&format!("No command in {:?}\n", values)); },
+                }
             },
+            Messages::BufLines => { self.app.mirrors.lock().unwrap().on_lines(&values); },
+            Messages::BufChangedtick => { self.app.mirrors.lock().unwrap().on_changedtick(&values); },
+            Messages::BufDetach => { self.app.mirrors.lock().unwrap().on_detach(&values); },
             Messages::Unknown(s) => { self.log("Inside Unknown branch\n"); },
         }
     }
 
     fn recv(&mut self) {
-        let receiver = self.nvim.session.start_event_loop_channel();
+        let receiver = self.nvim.session.start_event_loop_channel_handler(Requests { mirrors: self.app.mirrors.clone() });
         self.log("Opened receiver\n");
         // let (event, values) = receiver.recv().unwrap();
         for  (event, values) in receiver {
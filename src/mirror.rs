@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use neovim_lib::Value;
+
+use crate::lexer::State;
+use crate::profile::Profile;
+use crate::scanner::{self, LineIndex, Scanner};
+
+/// Buffer handles come as msgpack ext values whose payload is the buffer number, itself
+/// msgpack-encoded. This is what `nvim_get_current_buf` and the `nvim_buf_*_event` notifications
+/// send.
+pub fn buffer_id(value: &Value) -> Option<u64> {
+    match value {
+        Value::Ext(_, data) => rmpv::decode::read_value(&mut &data[..]).ok()?.as_u64(),
+        _ => value.as_u64(),
+    }
+}
+
+/// In-memory copy of an attached buffer, kept up to date from `nvim_buf_lines_event`.
+///
+/// lines: every line of the buffer.
+/// index: the index of every line, see `scanner::index_line`. Only the lines touched by an event
+/// are recomputed, and the ones after them while the state they start in changes, like when a
+/// block comment is opened.
+/// profile: the profile the index was built with. The whole index is rebuilt if the filetype of
+/// the buffer changes.
+/// changedtick: the `b:changedtick` the mirror corresponds to. None until the first event.
+#[derive(Debug, Default)]
+pub struct BufMirror {
+    lines: Vec<String>,
+    index: Vec<LineIndex>,
+    profile: Profile,
+    changedtick: Option<u64>,
+}
+
+impl BufMirror {
+
+    pub fn scanner(&self) -> Scanner<'_> {
+        Scanner::new(&self.lines, &self.index, 1)
+    }
+
+    /// Replace lines [first, last) with `data`. A negative last means "until the end", which is
+    /// what the initial event sent by `nvim_buf_attach` uses.
+    fn splice(&mut self, first: i64, last: i64, data: Vec<String>) {
+        let len = self.lines.len();
+        let first = (first.max(0) as usize).min(len);
+        let last = if last < 0 { len } else { (last as usize).min(len).max(first) };
+        let profile = &self.profile;
+        let end_of = |index: &[LineIndex], line: usize| {
+            if line == 0 { State::Code } else { index[line - 1].end.clone() }
+        };
+
+        // The state the line after the spliced ones used to start in.
+        let mut carried = end_of(&self.index, last);
+        let mut state = end_of(&self.index, first);
+        let index: Vec<LineIndex> = data.iter().map(|l| {
+            let index = scanner::index_line(l, profile, &state);
+            state = index.end.clone();
+            index
+        }).collect();
+        let mut next = first + data.len();
+        self.lines.splice(first..last, data);
+        self.index.splice(first..last, index);
+
+        while next < self.lines.len() && state != carried {
+            carried = self.index[next].end.clone();
+            self.index[next] = scanner::index_line(&self.lines[next], profile, &state);
+            state = self.index[next].end.clone();
+            next += 1;
+        }
+    }
+
+}
+
+/// Every buffer this process is attached to, by buffer number.
+#[derive(Debug, Default)]
+pub struct Mirrors {
+    bufs: HashMap<u64, BufMirror>,
+}
+
+impl Mirrors {
+
+    pub fn new() -> Mirrors {
+        Mirrors { bufs: HashMap::new() }
+    }
+
+    pub fn is_attached(&self, buf: u64) -> bool {
+        self.bufs.contains_key(&buf)
+    }
+
+    /// Register a buffer right after `nvim_buf_attach`. Its content arrives later as a regular
+    /// lines event.
+    pub fn attach(&mut self, buf: u64) {
+        self.bufs.entry(buf).or_default();
+    }
+
+    /// The mirror of `buf`, only if it is in sync with `changedtick`. Its index is rebuilt first if
+    /// it was built for another profile.
+    pub fn get(&mut self, buf: u64, changedtick: u64, profile: &Profile) -> Option<&BufMirror> {
+        let mirror = self.bufs.get_mut(&buf).filter(|m| m.changedtick == Some(changedtick))?;
+        if mirror.profile != *profile {
+            mirror.profile = profile.clone();
+            mirror.index = scanner::index_lines(&mirror.lines, profile);
+        }
+        Some(mirror)
+    }
+
+    /// Vimscript list of `[bufnr, changedtick]` for every filled mirror. A lookup compares the
+    /// current buffer against it to know whether lines have to be fetched.
+    pub fn in_sync_list(&self) -> String {
+        let entries: Vec<String> = self.bufs.iter()
+            .filter_map(|(id, m)| m.changedtick.map(|tick| format!("[{}, {}]", id, tick)))
+            .collect();
+        format!("[{}]", entries.join(", "))
+    }
+
+    /// nvim_buf_lines_event: [buf, changedtick, firstline, lastline, linedata, more]
+    pub fn on_lines(&mut self, values: &[Value]) {
+        if values.len() < 5 { return }
+        let mirror = match buffer_id(&values[0]).and_then(|id| self.bufs.get_mut(&id)) {
+            Some(mirror) => mirror,
+            None => return,
+        };
+        // A nil tick means only the display changed ('inccommand' previews), not the buffer.
+        let tick = match values[1].as_u64() {
+            Some(tick) => tick,
+            None => return,
+        };
+        let first = values[2].as_i64().unwrap_or(0);
+        let last = values[3].as_i64().unwrap_or(-1);
+        let data = values[4].as_array().map_or(Vec::new(), |array| {
+            array.iter().map(|v| v.as_str().unwrap_or("").to_string()).collect()
+        });
+        mirror.splice(first, last, data);
+        mirror.changedtick = Some(tick);
+    }
+
+    /// nvim_buf_changedtick_event: [buf, changedtick]
+    pub fn on_changedtick(&mut self, values: &[Value]) {
+        if values.len() < 2 { return }
+        if let Some(mirror) = buffer_id(&values[0]).and_then(|id| self.bufs.get_mut(&id)) {
+            mirror.changedtick = values[1].as_u64();
+        }
+    }
+
+    /// nvim_buf_detach_event: [buf]
+    pub fn on_detach(&mut self, values: &[Value]) {
+        if let Some(id) = values.first().and_then(buffer_id) {
+            self.bufs.remove(&id);
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Kind;
+    use crate::scanner::tests::profile;
+
+    /// A mirror of buffer 1 for C, after the initial event with `lines`.
+    fn mirror(lines: &[&str]) -> Mirrors {
+        let mut mirrors = Mirrors::new();
+        mirrors.attach(1);
+        mirrors.bufs.get_mut(&1).unwrap().profile = profile(&[("filetype", "c"), ("comments", "s1:/*,mb:*,ex:*/,://")]);
+        event(&mut mirrors, 0, -1, lines);
+        mirrors
+    }
+
+    /// Send a lines event replacing [first, last) with `lines`.
+    fn event(mirrors: &mut Mirrors, first: i64, last: i64, lines: &[&str]) {
+        let tick = mirrors.bufs[&1].changedtick.map_or(1, |tick| tick + 1);
+        let data = lines.iter().map(|l| Value::from(*l)).collect::<Vec<Value>>();
+        mirrors.on_lines(&[Value::from(1), Value::from(tick), Value::from(first), Value::from(last), Value::from(data), Value::from(false)]);
+    }
+
+    /// Check the mirror holds `lines`, indexed as if from scratch.
+    fn assert_mirrors(mirrors: &Mirrors, lines: &[&str]) {
+        let mirror = &mirrors.bufs[&1];
+        assert_eq!(mirror.lines, lines);
+        assert_eq!(mirror.index, scanner::index_lines(&mirror.lines, &mirror.profile));
+    }
+
+    const LINES: [&str; 4] = ["f(a,", "  b) // )", "g(c)", "h(d)"];
+
+    #[test]
+    fn initial_event_fills_the_mirror() {
+        let mirrors = mirror(&LINES);
+        assert_eq!(mirrors.bufs[&1].changedtick, Some(1));
+        assert_mirrors(&mirrors, &LINES);
+    }
+
+    #[test]
+    fn opening_a_block_comment_reindexes_what_follows() {
+        let mut mirrors = mirror(&LINES);
+        event(&mut mirrors, 1, 1, &["/*"]);
+        assert_mirrors(&mirrors, &["f(a,", "/*", "  b) // )", "g(c)", "h(d)"]);
+        assert!(mirrors.bufs[&1].index[4].skipped.iter().any(|s| matches!(s.kind, Kind::BlockComment(..))));
+
+        event(&mut mirrors, 1, 2, &[]);
+        assert_mirrors(&mirrors, &LINES);
+        assert!(mirrors.bufs[&1].index[3].skipped.is_empty());
+    }
+
+    #[test]
+    fn replacing_the_last_line() {
+        let mut mirrors = mirror(&LINES);
+        event(&mut mirrors, 1, 1, &["/*"]);
+        event(&mut mirrors, 4, 5, &["*/ h(e)"]);
+        assert_mirrors(&mirrors, &["f(a,", "/*", "  b) // )", "g(c)", "*/ h(e)"]);
+    }
+
+}
@@ -0,0 +1,191 @@
+use neovim_lib::Value;
+
+use crate::comments::Comments;
+use crate::keywords::{self, KeywordPair};
+use crate::lexer::Syntax;
+use crate::strategy::Strategy;
+
+/// Vimscript expression for everything a profile is built from, evaluated in the buffer.
+pub const SETTINGS_EXPR: &str = "{\
+    'filetype': &filetype, \
+    'comments': &comments, \
+    'commentstring': &commentstring, \
+    'pairs': get(g:, 'delinhere_pairs', {}), \
+    'keywords': get(g:, 'delinhere_keywords', {}), \
+    'block_comments': get(g:, 'delinhere_comments', {}), \
+    'strategy': get(g:, 'delinhere_strategy', {}), \
+    'hints': get(g:, 'delinhere_hints', 0)}";
+
+/// What the scanner looks for in a buffer, decided from its filetype and the user configuration.
+///
+/// tags: markup tags are pairs. Off outside markup filetypes, where `<T>` is a generic and not an
+/// element.
+/// html: tag names are compared ignoring case, `<DIV>` is closed by `</div>`. Off in XML and JSX,
+/// where they are case sensitive.
+/// latex: LaTeX environments and `\left`/`\right` are pairs, and backslash escaped brackets are
+/// not brackets.
+/// pairs: extra (open, close) delimiters declared by the user, see `Profile::new`.
+/// keywords: word delimiters, the defaults of the filetype and the ones declared by the user.
+/// comments: comment delimiters, from the comment options of the buffer and the extra block
+/// comments declared by the user.
+/// syntax: the lexical rules of the filetype, what is a string and what is code.
+/// strategy: which of the pairs around the cursor comes first, see `Profile::new`.
+/// hints: without a count, label the pairs around the cursor and let a key pick one, see
+/// `hints::pick`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub tags: bool,
+    pub html: bool,
+    pub latex: bool,
+    pub pairs: Vec<(String, String)>,
+    pub keywords: Vec<KeywordPair>,
+    pub comments: Comments,
+    pub syntax: Syntax,
+    pub strategy: Strategy,
+    pub hints: bool,
+}
+
+const HTML_FILETYPES: [&str; 4] = ["html", "php", "eruby", "htmldjango"];
+
+const LATEX_FILETYPES: [&str; 4] = ["tex", "plaintex", "context", "rnoweb"];
+
+const MARKUP_FILETYPES: [&str; 14] = [
+    "html", "xhtml", "xml", "svg", "xsd", "xslt", "vue", "svelte", "astro", "php", "eruby",
+    "htmldjango", "javascriptreact", "typescriptreact",
+];
+
+impl Profile {
+
+    /// Build the profile of a buffer from the result of `SETTINGS_EXPR`.
+    ///
+    /// `pairs` is the value of `g:delinhere_pairs`, a dictionary from filetype to a list of
+    /// [open, close] delimiters. The "*" entry applies to every filetype:
+    ///
+    ///     let g:delinhere_pairs = {
+    ///         \ '*': [['«', '»'], ['⟨', '⟩']],
+    ///         \ 'htmldjango': [['{{', '}}'], ['{%', '%}']],
+    ///         \ }
+    ///
+    /// `keywords` is the value of `g:delinhere_keywords`, with the same shape. Each side of a
+    /// keyword pair is a word or a list of words sharing their nesting:
+    ///
+    ///     let g:delinhere_keywords = {
+    ///         \ 'ruby': [[['def', 'do', 'class', 'module'], 'end']],
+    ///         \ 'tex': [['\begingroup', '\endgroup']],
+    ///         \ }
+    ///
+    /// `block_comments` is the value of `g:delinhere_comments`, with the same shape as `pairs`,
+    /// for block comments the comment options of a filetype don't know about:
+    ///
+    ///     let g:delinhere_comments = {'markdown': [['<!--', '-->']]}
+    ///
+    /// `strategy` is the value of `g:delinhere_strategy`, a strategy for every filetype or a
+    /// dictionary from filetype to strategy, see `Strategy`:
+    ///
+    ///     let g:delinhere_strategy = {'*': 'same_line', 'javascript': ['prefer', '{']}
+    pub fn new(settings: &Value) -> Profile {
+        let setting = |key: &str| -> &Value {
+            settings.as_map()
+                .and_then(|map| map.iter().find(|(k, _)| k.as_str() == Some(key)))
+                .map_or(&Value::Nil, |(_, v)| v)
+        };
+        let filetype = setting("filetype").as_str().unwrap_or("");
+        let pairs = setting("pairs");
+        let keywords = setting("keywords");
+
+        // Compound filetypes like "html.handlebars" count if any of their parts does.
+        let parts: Vec<&str> = filetype.split('.').collect();
+        let tags = parts.iter().any(|ft| MARKUP_FILETYPES.contains(ft));
+        let html = parts.iter().any(|ft| HTML_FILETYPES.contains(ft));
+        let latex = parts.iter().any(|ft| LATEX_FILETYPES.contains(ft));
+
+        let declared = Self::entries_for(pairs, &parts).into_iter()
+            .flat_map(Self::parse_pairs)
+            .collect();
+
+        let mut keyword_pairs: Vec<KeywordPair> = parts.iter()
+            .flat_map(|ft| keywords::defaults(ft))
+            .collect();
+        keyword_pairs.extend(Self::entries_for(keywords, &parts).into_iter().flat_map(Self::parse_keywords));
+
+        let mut comments = Comments::parse(
+            setting("comments").as_str().unwrap_or(""),
+            setting("commentstring").as_str().unwrap_or(""));
+        for (start, end) in Self::entries_for(setting("block_comments"), &parts).into_iter().flat_map(Self::parse_pairs) {
+            comments.add_block(start, end);
+        }
+
+        let syntax = Syntax { tags, ..Syntax::for_filetype(&parts) };
+
+        let strategy = setting("strategy");
+        let strategy = match strategy {
+            Value::Map(_) => Self::entry_for(strategy, &parts).map_or(Strategy::default(), Strategy::new),
+            _ => Strategy::new(strategy),
+        };
+
+        // Any non-zero number, like vimscript does.
+        let hints = setting("hints").as_i64().is_some_and(|hints| hints != 0);
+
+        Profile { tags, html, latex, pairs: declared, keywords: keyword_pairs, comments, syntax, strategy, hints }
+    }
+
+    /// The lists of a filetype dictionary that apply to `parts`, "*" included.
+    fn entries_for<'v>(config: &'v Value, parts: &[&str]) -> Vec<&'v Value> {
+        config.as_map().map_or(Vec::new(), |map| {
+            map.iter()
+                .filter(|(key, _)| key.as_str().is_some_and(|k| k == "*" || parts.contains(&k)))
+                .map(|(_, list)| list)
+                .collect()
+        })
+    }
+
+    /// The single value of a filetype dictionary that applies to `parts`. A filetype entry wins
+    /// over "*".
+    fn entry_for<'v>(config: &'v Value, parts: &[&str]) -> Option<&'v Value> {
+        let map = config.as_map()?;
+        let entry = |key: &str| map.iter().find(|(k, _)| k.as_str() == Some(key)).map(|(_, v)| v);
+        parts.iter().find_map(|ft| entry(ft)).or_else(|| entry("*"))
+    }
+
+    /// A word or a list of words.
+    fn parse_words(value: &Value) -> Vec<String> {
+        match value.as_str() {
+            Some(word) => vec![word.to_string()],
+            None => value.as_array().map_or(Vec::new(), |array| {
+                array.iter().filter_map(|v| v.as_str()).map(String::from).collect()
+            }),
+        }
+    }
+
+    fn parse_keywords(list: &Value) -> Vec<KeywordPair> {
+        list.as_array().map_or(Vec::new(), |array| {
+            array.iter().filter_map(|pair| {
+                let pair = pair.as_array()?;
+                let openers = Self::parse_words(pair.first()?);
+                let closers = Self::parse_words(pair.get(1)?);
+                let valid = |words: &Vec<String>| !words.is_empty() && words.iter().all(|w| !w.is_empty());
+                if valid(&openers) && valid(&closers) { Some(KeywordPair { openers, closers }) }
+                else { None }
+            }).collect()
+        })
+    }
+
+    fn parse_pairs(list: &Value) -> Vec<(String, String)> {
+        list.as_array().map_or(Vec::new(), |array| {
+            array.iter().filter_map(|pair| {
+                let pair = pair.as_array()?;
+                let open = pair.first()?.as_str()?;
+                let close = pair.get(1)?.as_str()?;
+                if open.is_empty() || close.is_empty() { None }
+                else { Some((open.to_string(), close.to_string())) }
+            }).collect()
+        })
+    }
+
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile::new(&Value::Nil)
+    }
+}
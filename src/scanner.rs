@@ -0,0 +1,845 @@
+use std::collections::HashMap;
+
+use crate::angle;
+use crate::keywords;
+use crate::latex::{self, Control};
+use crate::lexer::{self, Kind, Span, State};
+use crate::profile::Profile;
+use crate::tags::{self, Resumed, TagKind};
+use crate::utils::{BPairs, Pos};
+
+/// Number of lines fetched above and below the cursor. Pairs that open or close outside of this
+/// window are not found.
+pub const WINDOW: u64 = 300;
+
+/// A balanced bracket pair, with the same conventions as `Pos` (1-based line, 1-based byte
+/// column).
+///
+/// open, close: the first char of each delimiter.
+/// open_end: the last byte of the opening delimiter, which is on another line than open for a tag
+/// whose attributes span lines.
+/// close_len: the length in bytes of the closing delimiter, `)` is 1 long and `</div>` is 6.
+#[derive(Debug, Clone)]
+pub struct Pair {
+    pub bpair: BPairs,
+    pub open: Pos,
+    pub open_end: Pos,
+    pub close: Pos,
+    pub close_len: u64,
+}
+
+/// Which pairs a command looks at.
+///
+/// Here: the pairs around the cursor, or the one `Scanner::seek` finds when none of them is on
+/// the cursor line.
+/// Next: the pairs after the cursor, `dinh`.
+/// Last: the pairs before the cursor, `dilh`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Here,
+    Next,
+    Last,
+}
+
+impl Target {
+
+    pub fn from_arg(arg: &str) -> Target {
+        match arg {
+            "next" => Target::Next,
+            "last" => Target::Last,
+            _ => Target::Here,
+        }
+    }
+
+    /// The argument `from_arg` reads back as this target.
+    pub fn to_arg(self) -> &'static str {
+        match self {
+            Target::Here => "",
+            Target::Next => "next",
+            Target::Last => "last",
+        }
+    }
+
+}
+
+/// A delimiter found on a line. col is the 1-based byte column of its first char, like in `Pos`,
+/// and len its length in bytes. A tag whose attributes span lines is found on the line of its
+/// `>`: lines is then how many lines above it starts, col is the column of its `<` up there and
+/// len the length of its part on this line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bracket {
+    pub col: u64,
+    pub len: u64,
+    pub lines: u64,
+    pub bpair: BPairs,
+    pub open: bool,
+}
+
+impl Bracket {
+
+    /// Position of its last byte when it starts at `start`.
+    pub fn end(&self, start: Pos) -> Pos {
+        if self.lines == 0 {
+            Pos::new(start.line(), start.col() + self.len - 1)
+        }
+        else {
+            Pos::new(start.line() + self.lines, self.len)
+        }
+    }
+
+}
+
+/// What is cached for every line of a buffer.
+///
+/// brackets: every delimiter of the line, see `index_line`.
+/// skipped: the spans that are not code, strings and comments, see `lexer::lex`.
+/// end: where the line ends, which is where the next one starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineIndex {
+    pub brackets: Vec<Bracket>,
+    pub skipped: Vec<Span>,
+    pub end: State,
+}
+
+/// Index consecutive lines, the first one starting in code.
+pub fn index_lines(lines: &[String], profile: &Profile) -> Vec<LineIndex> {
+    let mut state = State::Code;
+    lines.iter().map(|line| {
+        let index = index_line(line, profile, &state);
+        state = index.end.clone();
+        index
+    }).collect()
+}
+
+/// Every delimiter on a single line, in order: brackets, quotes, tags, LaTeX environments, block
+/// comments and the custom and keyword pairs of the profile. `state` is where the previous line
+/// ended. Nothing inside strings, chars, regexes or comments is a delimiter, but the quotes of a
+/// string and the ends of a block comment are. This is what gets cached per line by the buffer
+/// mirror, so scanning never has to look at the rest of the text again.
+///
+/// A tag whose attributes go on to the next lines is found on the line of its `>`, see `Bracket`.
+/// The lines up to that one have nothing else.
+pub fn index_line(line: &str, profile: &Profile, state: &State) -> LineIndex {
+    let tag_pair = |name: &str| BPairs::Tag(if profile.html { name.to_lowercase() } else { name.to_string() });
+
+    // Where the code starts on this line, after the end of a tag left pending by the previous one.
+    let mut found = Vec::new();
+    let from = match state {
+        State::Tag(pending) => match tags::resume(line, pending) {
+            Resumed::Continues(pending) => {
+                return LineIndex { brackets: Vec::new(), skipped: Vec::new(), end: State::Tag(pending) }
+            },
+            Resumed::Closed(kind, b) => {
+                if kind != TagKind::Lone {
+                    let (col, lines) = (pending.col, pending.lines);
+                    found.push(Bracket { col, len: b as u64 + 1, lines, bpair: tag_pair(&pending.name), open: kind == TagKind::Open });
+                }
+                b + 1
+            },
+            Resumed::Dropped => 0,
+        },
+        _ => 0,
+    };
+    let state = if from > 0 { &State::Code } else { state };
+
+    let (mut skipped, mut end) = lexer::lex(&line[from..], &profile.syntax, &profile.comments, state);
+    for span in &mut skipped {
+        span.start += from;
+        span.end += from;
+    }
+    let in_skipped = |b: usize| skipped.iter().any(|s| s.contains(b));
+    let (mut tags, pending) = if profile.tags { tags::tags(line, from) } else { (Vec::new(), None) };
+    tags.retain(|t| !in_skipped(t.start));
+
+    // A tag that doesn't end on the line takes all of it from its `<` on.
+    let mut stop = line.len();
+    if let Some(pending) = pending.filter(|p| end == State::Code && !in_skipped(p.col as usize - 1)) {
+        stop = pending.col as usize - 1;
+        end = State::Tag(pending);
+    }
+    skipped.retain(|s| s.start < stop);
+
+    let angles: HashMap<usize, bool> = angle::angle_brackets(line).into_iter()
+        .filter(|(b, _)| !skipped.iter().any(|s| s.contains(*b)) && !tags.iter().any(|t| t.contains(*b)))
+        .collect();
+
+    // Custom pairs whose delimiters are the same on both sides work like quotes: the first one
+    // opens, the next one closes. These are the ones currently open.
+    let mut open_symmetric: Vec<&(String, String)> = Vec::new();
+    let mut skip_to = from;
+
+    for (b, ch) in line.char_indices() {
+        if b >= stop {
+            break
+        }
+        if b < skip_to {
+            continue
+        }
+        let col = b as u64 + 1;
+        let bracket = |bpair: BPairs, len: usize, open: bool| {
+            Some((Some(Bracket { col, len: len as u64, lines: 0, bpair, open }), len))
+        };
+
+        if let Some(span) = skipped.iter().find(|s| s.start == b) {
+            let bpair = match &span.kind {
+                Kind::String(bpair) => bpair.clone(),
+                Kind::BlockComment(start, end) => Some(BPairs::Comment(start.clone(), end.clone())),
+                _ => None,
+            };
+            if let Some(bpair) = bpair {
+                if span.open_len > 0 {
+                    found.push(Bracket { col, len: span.open_len as u64, lines: 0, bpair: bpair.clone(), open: true });
+                }
+                if span.close_len > 0 {
+                    let col = (span.end - span.close_len) as u64 + 1;
+                    found.push(Bracket { col, len: span.close_len as u64, lines: 0, bpair, open: false });
+                }
+            }
+            skip_to = span.end;
+            continue
+        }
+
+        // The delimiter starting at b, if any, and how many bytes it takes.
+        let token = if let Some(t) = tags.iter().find(|t| t.start == b && t.kind != TagKind::Lone) {
+            bracket(tag_pair(&t.name), t.end - t.start + 1, t.kind == TagKind::Open)
+        }
+        else if let Some(control) = latex::control_at(line, b).filter(|_| profile.latex) {
+            match control {
+                Control::Delimiter(bpair, len, open) => bracket(bpair, len, open),
+                Control::Escape(len) => Some((None, len)),
+            }
+        }
+        else if let Some((pair, len, open)) = custom_at(&line[b..], &profile.pairs, &open_symmetric) {
+            if pair.0 == pair.1 {
+                if open { open_symmetric.push(pair) } else { open_symmetric.retain(|p| *p != pair) }
+            }
+            bracket(BPairs::Custom(pair.0.clone(), pair.1.clone()), len, open)
+        }
+        else if let Some((pair, len, open)) = keywords::keyword_at(line, b, &profile.keywords) {
+            bracket(BPairs::Keyword(pair.openers.clone(), pair.closers.clone()), len, open)
+        }
+        else if let Some(bpair) = BPairs::from_open_char(ch) {
+            bracket(bpair, ch.len_utf8(), true)
+        }
+        else if let Some(bpair) = BPairs::from_close_char(ch) {
+            bracket(bpair, ch.len_utf8(), false)
+        }
+        else if let Some(open) = angles.get(&b) {
+            bracket(BPairs::Angle, 1, *open)
+        }
+        else {
+            None
+        };
+
+        if let Some((delimiter, len)) = token {
+            found.extend(delimiter);
+            skip_to = b + len;
+        }
+    }
+
+    LineIndex { brackets: found, skipped, end }
+}
+
+/// The custom delimiter `rest` starts with, if any, as (pair, length, is_open). The longest one
+/// wins, so `{{` is found before `{` when both are declared.
+fn custom_at<'p>(rest: &str, pairs: &'p [(String, String)], open_symmetric: &[&(String, String)])
+    -> Option<(&'p (String, String), usize, bool)> {
+    pairs.iter().filter_map(|pair| {
+        if pair.0 == pair.1 {
+            let open = !open_symmetric.contains(&pair);
+            rest.starts_with(&pair.0[..]).then_some((pair, pair.0.len(), open))
+        }
+        else if rest.starts_with(&pair.0[..]) {
+            Some((pair, pair.0.len(), true))
+        }
+        else if rest.starts_with(&pair.1[..]) {
+            Some((pair, pair.1.len(), false))
+        }
+        else {
+            None
+        }
+    }).max_by_key(|(_, len, _)| *len)
+}
+
+/// Finds bracket pairs on a range of buffer lines without going back to neovim.
+///
+/// lines: the buffer lines, without trailing newlines.
+/// index: the index of every line in lines.
+/// first_line: the buffer line number (1-based) of lines[0].
+pub struct Scanner<'a> {
+    lines: &'a [String],
+    index: &'a [LineIndex],
+    first_line: u64,
+}
+
+impl<'a> Scanner<'a> {
+
+    pub fn new(lines: &'a [String], index: &'a [LineIndex], first_line: u64) -> Scanner<'a> {
+        Scanner { lines, index, first_line }
+    }
+
+    /// Whether lines `from` to `to` have all been fetched.
+    pub fn has_lines(&self, from: u64, to: u64) -> bool {
+        self.first_line <= from && to < self.first_line + self.lines.len() as u64
+    }
+
+    /// Lines that have been fetched, for consumers that need the text between the brackets.
+    pub fn lines_between(&self, from: u64, to: u64) -> &'a [String] {
+        let from = (from - self.first_line) as usize;
+        let to = (to - self.first_line) as usize;
+        &self.lines[from..=to]
+    }
+
+    /// The spans that are not code on the same lines as `lines_between`.
+    pub fn skipped_between(&self, from: u64, to: u64) -> Vec<&'a [Span]> {
+        let from = (from - self.first_line) as usize;
+        let to = (to - self.first_line) as usize;
+        self.index[from..=to].iter().map(|i| &i.skipped[..]).collect()
+    }
+
+    /// The brackets of the line at `i` in the index with the position they start at. One that
+    /// starts above the fetched lines is left out.
+    fn brackets_of(index: &'a [LineIndex], first_line: u64, i: usize)
+        -> impl DoubleEndedIterator<Item = (Pos, &'a Bracket)> + 'a {
+        let line = first_line + i as u64;
+        index[i].brackets.iter()
+            .filter(move |b| b.lines <= i as u64)
+            .map(move |b| (Pos::new(line - b.lines, b.col), b))
+    }
+
+    /// Every bracket strictly before the cursor, walking backwards. When the cursor is in the
+    /// attributes of a tag spanning lines, the walk starts on the line of its `>`. The lines before
+    /// that one are empty in the index, see `index_line`.
+    fn brackets_before(&self, cursor: Pos) -> impl Iterator<Item = (Pos, &'a Bracket)> + 'a {
+        let cursor_idx = (cursor.line() - self.first_line) as usize;
+        let last = (cursor_idx + 1..self.index.len())
+            .find(|i| !self.index[*i].brackets.is_empty())
+            .filter(|i| self.index[*i].brackets[0].lines >= (i - cursor_idx) as u64)
+            .unwrap_or(cursor_idx);
+        let (index, first_line) = (self.index, self.first_line);
+        (0..=last).rev().flat_map(move |i| {
+            Self::brackets_of(index, first_line, i).rev().filter(move |(pos, _)| *pos < cursor)
+        })
+    }
+
+    /// Every bracket strictly after the cursor, walking forwards.
+    fn brackets_after(&self, cursor: Pos) -> impl Iterator<Item = (Pos, &'a Bracket)> + 'a {
+        let cursor_idx = (cursor.line() - self.first_line) as usize;
+        let (index, first_line) = (self.index, self.first_line);
+        (cursor_idx..index.len()).flat_map(move |i| {
+            Self::brackets_of(index, first_line, i).filter(move |(pos, _)| *pos > cursor)
+        })
+    }
+
+    /// The pair of the opener starting at `open` and the closer at `close`.
+    fn pair(open: Pos, opener: &Bracket, close: Pos, closer: &Bracket) -> Pair {
+        Pair { bpair: opener.bpair.clone(), open, open_end: opener.end(open), close, close_len: closer.len }
+    }
+
+    /// Walk backwards from the cursor keeping a depth per bracket type. Every opener whose type is
+    /// not balanced by a closer seen on the way may be the innermost enclosing one, the first is
+    /// the most likely. The char under the cursor is not considered, see `pair_at` for that.
+    fn open_candidates(&self, cursor: Pos) -> impl Iterator<Item = (Pos, &'a Bracket)> + 'a {
+        let mut depth: HashMap<&'a BPairs, usize> = HashMap::new();
+        self.brackets_before(cursor).filter(move |(_, bracket)| {
+            let bracket: &'a Bracket = bracket;
+            let d = depth.entry(&bracket.bpair).or_insert(0);
+            if !bracket.open {
+                *d += 1;
+                false
+            }
+            else if *d == 0 {
+                true
+            }
+            else {
+                *d -= 1;
+                false
+            }
+        })
+    }
+
+    /// Walk forwards from the cursor until the closer matching `bpair` at depth zero.
+    fn find_close(&self, bpair: &BPairs, cursor: Pos) -> Option<(&'a Bracket, Pos)> {
+        let mut depth: usize = 0;
+        for (pos, bracket) in self.brackets_after(cursor) {
+            if bracket.bpair != *bpair {
+                continue
+            }
+            if bracket.open {
+                depth += 1;
+            }
+            else if depth == 0 {
+                return Some((bracket, pos))
+            }
+            else {
+                depth -= 1;
+            }
+        }
+        None
+    }
+
+    /// Walk backwards from the closer at `close` until the opener matching `bpair` at depth zero.
+    fn find_open_of(&self, bpair: &BPairs, close: Pos) -> Option<(&'a Bracket, Pos)> {
+        let mut depth: usize = 0;
+        for (pos, bracket) in self.brackets_before(close) {
+            if bracket.bpair != *bpair {
+                continue
+            }
+            if !bracket.open {
+                depth += 1;
+            }
+            else if depth == 0 {
+                return Some((bracket, pos))
+            }
+            else {
+                depth -= 1;
+            }
+        }
+        None
+    }
+
+    /// The innermost pair enclosing the cursor, if both of its ends are inside the scanned lines.
+    /// A delimiter under the cursor selects the pair it belongs to: in `f(a)(b)` the cursor on the
+    /// first `)` means `(a)` and on the second `(` it means `(b)`.
+    pub fn enclosing(&self, cursor: Pos) -> Option<Pair> {
+        self.pair_at(cursor).or_else(|| self.surrounding(cursor, cursor))
+    }
+
+    /// The innermost pair opening before `start` and closing after `end`. An opener with no
+    /// closer, or whose closer would leave the pair unbalanced, is a stray one in half-written code
+    /// and the next candidate is tried instead.
+    fn surrounding(&self, start: Pos, end: Pos) -> Option<Pair> {
+        self.open_candidates(start).find_map(|(open, opener)| {
+            let (closer, close) = self.find_close(&opener.bpair, end)?;
+            let pair = Self::pair(open, opener, close, closer);
+            self.is_balanced(&pair).then_some(pair)
+        })
+    }
+
+    /// Whether no delimiter between the ends of `pair` closes something opened before it. In
+    /// `f(a, [b)` the `[` has no business with a `]` after the `)`.
+    fn is_balanced(&self, pair: &Pair) -> bool {
+        let mut depth: HashMap<&BPairs, usize> = HashMap::new();
+        self.brackets_after(pair.open)
+            .take_while(|(pos, _)| *pos < pair.close)
+            .all(|(_, bracket)| {
+                let d = depth.entry(&bracket.bpair).or_insert(0);
+                if bracket.open {
+                    *d += 1;
+                    true
+                }
+                else if *d == 0 {
+                    false
+                }
+                else {
+                    *d -= 1;
+                    true
+                }
+            })
+    }
+
+    /// The pair of the delimiter under the cursor, if there is one.
+    fn pair_at(&self, cursor: Pos) -> Option<Pair> {
+        let (pos, bracket) = self.brackets_before(Pos::new(cursor.line(), cursor.col() + 1))
+            .take_while(|(pos, b)| b.end(*pos).line() >= cursor.line())
+            .find(|(pos, b)| cursor <= b.end(*pos))?;
+        let (opener, open, closer, close) = if bracket.open {
+            let (closer, close) = self.find_close(&bracket.bpair, pos)?;
+            (bracket, pos, closer, close)
+        }
+        else {
+            let (opener, open) = self.find_open_of(&bracket.bpair, pos)?;
+            (opener, open, bracket, pos)
+        };
+        let pair = Self::pair(open, opener, close, closer);
+        self.is_balanced(&pair).then_some(pair)
+    }
+
+    /// The line comment the cursor is in, as a pair going from its leader to the end of its last
+    /// line. A comment that has a line to itself is extended over the comment lines around it.
+    /// The opening delimiter is the leader with the blank that follows it, and the closing one is
+    /// empty, right after the end of the last line.
+    pub fn comment_run(&self, cursor: Pos) -> Option<Pair> {
+        let comment = self.line_comment(cursor.line()).filter(|c| (c.start as u64) < cursor.col())?;
+
+        let mut first = cursor.line();
+        let mut last = cursor.line();
+        if self.full_line_comment(cursor.line()).is_some() {
+            let last_fetched = self.first_line + self.lines.len() as u64 - 1;
+            while first > self.first_line && self.full_line_comment(first - 1).is_some() {
+                first -= 1;
+            }
+            while last < last_fetched && self.full_line_comment(last + 1).is_some() {
+                last += 1;
+            }
+        }
+
+        let open = if first == cursor.line() { comment } else { self.full_line_comment(first)? };
+        let blank = self.line(first)[open.start + open.open_len..].starts_with(' ') as usize;
+
+        Some(Pair {
+            bpair: BPairs::LineComment,
+            open: Pos::new(first, open.start as u64 + 1),
+            open_end: Pos::new(first, (open.start + open.open_len + blank) as u64),
+            close: Pos::new(last, self.line(last).len() as u64 + 1),
+            close_len: 0,
+        })
+    }
+
+    fn line_comment(&self, line: u64) -> Option<&'a Span> {
+        self.index[(line - self.first_line) as usize].skipped.iter().find(|s| s.kind == Kind::LineComment)
+    }
+
+    /// The line comment of a line that is nothing but a comment.
+    fn full_line_comment(&self, line: u64) -> Option<&'a Span> {
+        let text = self.line(line);
+        let indent = text.len() - text.trim_start().len();
+        self.line_comment(line).filter(|c| c.start == indent)
+    }
+
+    /// Every pair opening after the cursor, nearest first.
+    pub fn next_pairs(&self, cursor: Pos) -> impl Iterator<Item = Pair> + '_ {
+        self.brackets_after(cursor)
+            .filter(|(_, bracket)| bracket.open)
+            .filter_map(move |(open, opener)| {
+                let (closer, close) = self.find_close(&opener.bpair, open)?;
+                let pair = Self::pair(open, opener, close, closer);
+                self.is_balanced(&pair).then_some(pair)
+            })
+    }
+
+    /// Every pair closing before the cursor, nearest first.
+    pub fn last_pairs(&self, cursor: Pos) -> impl Iterator<Item = Pair> + '_ {
+        self.brackets_before(cursor)
+            .filter(|(_, bracket)| !bracket.open)
+            .filter_map(move |(close, closer)| {
+                let (opener, open) = self.find_open_of(&closer.bpair, close)?;
+                let pair = Self::pair(open, opener, close, closer);
+                self.is_balanced(&pair).then_some(pair)
+            })
+    }
+
+    /// Like targets.vim, the pair to act on when none is around the cursor on its line: the next
+    /// one on the line, or else the last one.
+    pub fn seek(&self, cursor: Pos) -> Option<Pair> {
+        let on_line = |pair: &Pair| pair.open.line() == cursor.line() && pair.close.line() == cursor.line();
+        self.next_pairs(cursor).next().filter(on_line)
+            .or_else(|| self.last_pairs(cursor).next().filter(on_line))
+    }
+
+    /// Every pair around the text from `start` to `end`, comments included, innermost first. A
+    /// cursor is the text from itself to itself, a Visual selection goes from its first char to
+    /// its last one. The count of a command picks one of them: `2dih` acts on the second.
+    pub fn stack(&self, start: Pos, end: Pos) -> Vec<Pair> {
+        let mut stack: Vec<Pair> = Vec::new();
+        let cursor = start;
+        let mut next = self.enclosing(cursor);
+        while let Some(pair) = next {
+            next = self.surrounding(pair.open, pair.close);
+            stack.push(pair);
+        }
+        if let Some(comment) = self.comment_run(cursor) {
+            let at = stack.iter().position(|p| p.open < comment.open).unwrap_or(stack.len());
+            stack.insert(at, comment);
+        }
+        // The pairs around the start that close before the end only hold part of the text.
+        stack.retain(|pair| self.around(pair).1 >= end);
+        stack
+    }
+
+    /// Number of chars from `from`, included, to `to`, excluded. A line break counts as one. 0 if
+    /// `to` isn't after `from`.
+    pub fn distance(&self, from: Pos, to: Pos) -> u64 {
+        if to <= from {
+            return 0
+        }
+        let chars = |line: u64, start: u64, end: Option<u64>| -> u64 {
+            let text = self.line(line);
+            let end = end.map_or(text.len(), |e| (e as usize - 1).min(text.len()));
+            text.get(start as usize - 1..end).map_or(0, |t| t.chars().count() as u64)
+        };
+        if from.line() == to.line() {
+            return chars(from.line(), from.col(), Some(to.col()))
+        }
+        let between: u64 = (from.line() + 1..to.line()).map(|l| chars(l, 1, None) + 1).sum();
+        chars(from.line(), from.col(), None) + 1 + between + chars(to.line(), 1, Some(to.col()))
+    }
+
+    fn line(&self, line: u64) -> &'a str {
+        &self.lines[(line - self.first_line) as usize]
+    }
+
+    /// First and last char of the pair, delimiters included. The last one is given by the position
+    /// of its first byte.
+    pub fn around(&self, pair: &Pair) -> (Pos, Pos) {
+        let close_end = pair.close.col() + pair.close_len - 1;
+        (pair.open, self.char_start(pair.close.line(), close_end))
+    }
+
+    /// First and last char between the delimiters, None when there is nothing in between. When the
+    /// opening delimiter ends its line the inside starts on the next one.
+    pub fn inside(&self, pair: &Pair) -> Option<(Pos, Pos)> {
+        let after_open = pair.open_end.col() + 1;
+        let start = if after_open as usize > self.line(pair.open_end.line()).len() {
+            Pos::new(pair.open_end.line() + 1, 1)
+        }
+        else {
+            Pos::new(pair.open_end.line(), after_open)
+        };
+
+        let end = if pair.close.col() > 1 {
+            self.char_start(pair.close.line(), pair.close.col() - 1)
+        }
+        else {
+            // The closing delimiter starts its line: the inside ends with the previous line.
+            let prev = pair.close.line() - 1;
+            let len = self.line(prev).len() as u64;
+            self.char_start(prev, len.max(1))
+        };
+
+        if start < pair.close && start <= end { Some((start, end)) } else { None }
+    }
+
+    /// The text from `start` to the char at `end`, included, line by line.
+    pub fn text(&self, start: Pos, end: Pos) -> Vec<String> {
+        let last = self.line(end.line());
+        let end_byte = end.col() as usize - 1 + last.get(end.col() as usize - 1..)
+            .and_then(|rest| rest.chars().next())
+            .map_or(0, char::len_utf8);
+        (start.line()..=end.line()).map(|l| {
+            let line = self.line(l);
+            let from = if l == start.line() { start.col() as usize - 1 } else { 0 };
+            let to = if l == end.line() { end_byte } else { line.len() };
+            line.get(from..to).unwrap_or("").to_string()
+        }).collect()
+    }
+
+    /// The lines strictly between the delimiters of `pair` when its inside is made of them, like
+    /// vim's `i(` sees it: the opening delimiter ends its line and only indent comes before the
+    /// closing one. None otherwise, or when there is no line in between.
+    pub fn inside_lines(&self, pair: &Pair) -> Option<(u64, u64)> {
+        let (first, last) = (pair.open_end.line() + 1, pair.close.line() - 1);
+        let open_ends = pair.open_end.col() as usize >= self.line(pair.open_end.line()).len();
+        let indent = self.line(pair.close.line())[..pair.close.col() as usize - 1].trim().is_empty();
+        (first <= last && open_ends && indent).then_some((first, last))
+    }
+
+    /// The char at `pos`, or the last one of its line when `pos` is past its end, like the `'>`
+    /// mark of a linewise selection is.
+    pub fn clamp(&self, pos: Pos) -> Pos {
+        let len = self.line(pos.line()).len() as u64;
+        self.char_start(pos.line(), pos.col().min(len.max(1)))
+    }
+
+    /// The char before the one at `pos`, the last one of the previous line when `pos` starts its
+    /// line.
+    pub fn char_before(&self, pos: Pos) -> Pos {
+        if pos.col() > 1 {
+            self.char_start(pos.line(), pos.col() - 1)
+        }
+        else if pos.line() > self.first_line {
+            self.clamp(Pos::new(pos.line() - 1, u64::MAX))
+        }
+        else {
+            pos
+        }
+    }
+
+    /// Position right after the char at `pos`, past the end of its line for the last one.
+    pub fn char_after(&self, pos: Pos) -> Pos {
+        let len = self.line(pos.line()).get(pos.col() as usize - 1..)
+            .and_then(|rest| rest.chars().next())
+            .map_or(1, char::len_utf8);
+        Pos::new(pos.line(), pos.col() + len as u64)
+    }
+
+    /// Position of the first byte of the char that byte column `col` belongs to.
+    fn char_start(&self, line: u64, col: u64) -> Pos {
+        let text = self.line(line);
+        let start = text.char_indices()
+            .map(|(b, _)| b as u64 + 1)
+            .take_while(|b| *b <= col)
+            .last()
+            .unwrap_or(1);
+        Pos::new(line, start)
+    }
+
+}
+
+#[cfg(test)]
+pub mod tests {
+    use neovim_lib::Value;
+
+    use super::*;
+
+    /// The profile `Profile::new` builds from these settings, like `[("filetype", "lua")]`.
+    pub fn profile(settings: &[(&str, &str)]) -> Profile {
+        Profile::new(&Value::Map(settings.iter().map(|(k, v)| (Value::from(*k), Value::from(*v))).collect()))
+    }
+
+    /// Lines and their index, for the tests of anything found by scanning.
+    pub struct Fixture {
+        lines: Vec<String>,
+        index: Vec<LineIndex>,
+    }
+
+    impl Fixture {
+
+        pub fn new(lines: &[&str], profile: &Profile) -> Fixture {
+            let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+            let index = index_lines(&lines, profile);
+            Fixture { lines, index }
+        }
+
+        /// Lines with the default profile, no filetype.
+        pub fn plain(lines: &[&str]) -> Fixture {
+            Fixture::new(lines, &Profile::default())
+        }
+
+        pub fn scanner(&self) -> Scanner<'_> {
+            Scanner::new(&self.lines, &self.index, 1)
+        }
+
+        /// The (open, close) columns of the pairs `stack` finds from `start` to `end`.
+        pub fn stack(&self, start: Pos, end: Pos) -> Vec<(u64, u64)> {
+            self.scanner().stack(start, end).iter().map(|pair| (pair.open.col(), pair.close.col())).collect()
+        }
+
+    }
+
+    /// The (open, close) columns of the pair `enclosing` finds on a single line, cursor at `col`.
+    fn enclosing_on(line: &str, col: u64) -> Option<(u64, u64)> {
+        let fixture = Fixture::plain(&[line]);
+        fixture.scanner().enclosing(Pos::new(1, col)).map(|pair| (pair.open.col(), pair.close.col()))
+    }
+
+    #[test]
+    fn cursor_on_open_selects_its_pair() {
+        // f(a, (b), c)
+        // 123456789012
+        assert_eq!(enclosing_on("f(a, (b), c)", 6), Some((6, 8)));
+        assert_eq!(enclosing_on("f(a, (b), c)", 2), Some((2, 12)));
+    }
+
+    #[test]
+    fn cursor_on_close_selects_its_pair() {
+        assert_eq!(enclosing_on("f(a, (b), c)", 8), Some((6, 8)));
+        assert_eq!(enclosing_on("f(a, (b), c)", 12), Some((2, 12)));
+    }
+
+    #[test]
+    fn adjacent_close_open() {
+        // g(a)(b)
+        // 1234567
+        assert_eq!(enclosing_on("g(a)(b)", 4), Some((2, 4)));
+        assert_eq!(enclosing_on("g(a)(b)", 5), Some((5, 7)));
+        // [x(a)(b)]
+        // 123456789
+        assert_eq!(enclosing_on("[x(a)(b)]", 5), Some((3, 5)));
+        assert_eq!(enclosing_on("[x(a)(b)]", 6), Some((6, 8)));
+    }
+
+    #[test]
+    fn cursor_between_pairs_is_in_the_outer_one() {
+        // [(a) (b)]
+        // 123456789
+        assert_eq!(enclosing_on("[(a) (b)]", 5), Some((1, 9)));
+    }
+
+    #[test]
+    fn stray_opener_is_skipped() {
+        // f(a, [b c) + x[0]
+        // 12345678901234567
+        assert_eq!(enclosing_on("f(a, [b c) + x[0]", 8), Some((2, 10)));
+    }
+
+    #[test]
+    fn unclosed_pair_selects_nothing() {
+        assert_eq!(enclosing_on("f(a, b", 4), None);
+    }
+
+    #[test]
+    fn distance_counts_chars_and_line_breaks() {
+        let fixture = Fixture::plain(&["aé(", "xyz", ")"]);
+        let scanner = fixture.scanner();
+        assert_eq!(scanner.distance(Pos::new(1, 1), Pos::new(1, 4)), 2);
+        assert_eq!(scanner.distance(Pos::new(1, 4), Pos::new(3, 1)), 6);
+        assert_eq!(scanner.distance(Pos::new(3, 1), Pos::new(1, 1)), 0);
+    }
+
+    #[test]
+    fn stack_goes_outwards() {
+        let fixture = Fixture::plain(&["a(b[c(d)e]f)"]);
+        assert_eq!(fixture.stack(Pos::new(1, 7), Pos::new(1, 7)), vec![(6, 8), (4, 10), (2, 12)]);
+    }
+
+    #[test]
+    fn inside_made_of_lines() {
+        let fixture = Fixture::plain(&["f(", "  a,", "  b", "  )", "g(x,", "y)"]);
+        let scanner = fixture.scanner();
+        let inside = |line: u64| scanner.enclosing(Pos::new(line, 1)).and_then(|pair| scanner.inside_lines(&pair));
+        assert_eq!(inside(2), Some((2, 3)));
+        assert_eq!(inside(6), None);
+    }
+
+    #[test]
+    fn text_ends_with_the_whole_last_char() {
+        let fixture = Fixture::plain(&["a(bé", "cd)"]);
+        let scanner = fixture.scanner();
+        assert_eq!(scanner.text(Pos::new(1, 3), Pos::new(1, 4)), vec!["bé"]);
+        assert_eq!(scanner.text(Pos::new(1, 4), Pos::new(2, 2)), vec!["é", "cd"]);
+    }
+
+    #[test]
+    fn stack_of_a_span_holds_all_of_it() {
+        let fixture = Fixture::plain(&["a(b[c(d)e]f)"]);
+        let stack = |start: u64, end: u64| fixture.stack(Pos::new(1, start), Pos::new(1, end));
+        assert_eq!(stack(5, 9), vec![(4, 10), (2, 12)]);
+        assert_eq!(stack(7, 11), vec![(2, 12)]);
+        assert_eq!(stack(4, 10), vec![(4, 10), (2, 12)]);
+    }
+
+    #[test]
+    fn closing_tags_start_no_regex() {
+        let stack = |filetype: &str, line: &str, col: u64| {
+            Fixture::new(&[line], &profile(&[("filetype", filetype)])).stack(Pos::new(1, col), Pos::new(1, col))
+        };
+        // return <div><p>a</p><b>c</b></div>;
+        // 1234567890123456789012345678901234
+        assert_eq!(stack("javascriptreact", "return <div><p>a</p><b>c</b></div>;", 24), vec![(21, 25), (8, 29)]);
+        // <ul><li>a</li><li>b</li></ul>
+        // 123456789012345678901234567890
+        assert_eq!(stack("typescriptreact", "<ul><li>a</li><li>b</li></ul>", 19), vec![(15, 20), (1, 25)]);
+    }
+
+    #[test]
+    fn open_tags_span_lines_up_to_their_close() {
+        let fixture = Fixture::new(
+            &["return (", "  <div", "    className=\"x\">", "    <p>a</p>", "  </div>", ");"],
+            &profile(&[("filetype", "javascriptreact")]));
+        let scanner = fixture.scanner();
+        let ends = |pair: &Pair| (pair.open, pair.open_end, pair.close);
+        let stack: Vec<_> = scanner.stack(Pos::new(4, 8), Pos::new(4, 8)).iter().map(ends).collect();
+        assert_eq!(stack, vec![
+            (Pos::new(4, 5), Pos::new(4, 7), Pos::new(4, 9)),
+            (Pos::new(2, 3), Pos::new(3, 18), Pos::new(5, 3)),
+            (Pos::new(1, 8), Pos::new(1, 8), Pos::new(6, 1)),
+        ]);
+        // In the attributes, the tag is the one under the cursor.
+        let div = scanner.enclosing(Pos::new(3, 6)).unwrap();
+        assert_eq!(ends(&div), (Pos::new(2, 3), Pos::new(3, 18), Pos::new(5, 3)));
+        assert_eq!(scanner.inside_lines(&div), Some((4, 4)));
+    }
+
+    #[test]
+    fn html_tag_names_ignore_case() {
+        let stack = |filetype: &str| {
+            Fixture::new(&["<DIV><p>a</P></div>"], &profile(&[("filetype", filetype)])).stack(Pos::new(1, 9), Pos::new(1, 9))
+        };
+        // <DIV><p>a</P></div>
+        // 1234567890123456789
+        assert_eq!(stack("html"), vec![(6, 10), (1, 14)]);
+        assert_eq!(stack("xml"), vec![]);
+    }
+
+}
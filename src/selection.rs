@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use crate::scanner::Scanner;
+use crate::utils::Pos;
+
+/// A range of chars: its first and last one, the last one given by the position of its first
+/// byte, like `Scanner::around` and `Scanner::inside` give them.
+pub type Range = (Pos, Pos);
+
+/// A Visual selection: its range and its mode as `visualmode()` gives it, "v", "V" or CTRL-V.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub range: Range,
+    pub mode: String,
+}
+
+/// The last Visual selection as the plugin sends it.
+///
+/// range: the `'<` and `'>` marks.
+/// mode: `visualmode()`.
+/// exclusive: 'selection' is exclusive. The `'>` mark of a charwise or blockwise selection is
+/// then right after its last char.
+#[derive(Debug, Clone)]
+pub struct Marks {
+    pub range: Range,
+    pub mode: String,
+    pub exclusive: bool,
+}
+
+impl Marks {
+
+    /// The selection the marks hold. A linewise one goes from the first char of its first line to
+    /// the last char of its last line.
+    pub fn selection(&self, scanner: &Scanner) -> Selection {
+        let (start, end) = self.range;
+        let end = if self.exclusive && self.mode != "V" && start < end { scanner.char_before(end) } else { end };
+        Selection { range: (scanner.clamp(start), scanner.clamp(end)), mode: self.mode.clone() }
+    }
+
+    /// The marks that hold `selection`, the other way around from `selection`.
+    pub fn of(scanner: &Scanner, selection: &Selection, exclusive: bool) -> Range {
+        let (start, end) = selection.range;
+        (start, if exclusive && selection.mode != "V" { scanner.char_after(end) } else { end })
+    }
+
+}
+
+/// The selections `ah` grew, per window, so `sh` can go back through them.
+///
+/// windows: from window id to the selections grown there, the one it all started from first and
+/// the current one last.
+#[derive(Debug, Default)]
+pub struct History {
+    windows: HashMap<u64, Vec<Selection>>,
+}
+
+impl History {
+
+    pub fn new() -> History {
+        History { windows: HashMap::new() }
+    }
+
+    /// Remember that `from` grew into `to` in `window`. When `from` is not the last selection
+    /// grown there, the user moved on and the history starts over from it.
+    pub fn grow(&mut self, window: u64, from: Selection, to: Selection) {
+        let history = self.windows.entry(window).or_default();
+        if history.last() != Some(&from) {
+            history.clear();
+            history.push(from);
+        }
+        history.push(to);
+    }
+
+    /// The selection `current` was grown from in `window`, which becomes the last one. None when
+    /// `current` is not the last selection grown there.
+    pub fn shrink(&mut self, window: u64, current: &Selection) -> Option<Selection> {
+        let history = self.windows.get_mut(&window)?;
+        if history.len() < 2 || history.last() != Some(current) {
+            return None
+        }
+        history.pop();
+        history.last().cloned()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::tests::Fixture;
+
+    fn selection(start: (u64, u64), end: (u64, u64), mode: &str) -> Selection {
+        Selection { range: (Pos::new(start.0, start.1), Pos::new(end.0, end.1)), mode: mode.to_string() }
+    }
+
+    #[test]
+    fn shrink_goes_back_through_what_grew() {
+        let mut history = History::new();
+        let line = selection((2, 1), (2, 5), "V");
+        let (inside, around) = (selection((1, 3), (3, 1), "v"), selection((1, 2), (3, 2), "v"));
+        history.grow(1, line.clone(), inside.clone());
+        history.grow(1, inside.clone(), around.clone());
+        assert_eq!(history.shrink(1, &around), Some(inside.clone()));
+        assert_eq!(history.shrink(1, &inside), Some(line.clone()));
+        assert_eq!(history.shrink(1, &line), None);
+    }
+
+    #[test]
+    fn another_selection_starts_over() {
+        let mut history = History::new();
+        let (first, grown) = (selection((1, 3), (1, 3), "v"), selection((1, 2), (1, 6), "v"));
+        history.grow(1, first.clone(), grown.clone());
+        // Moved away, then grew from there.
+        let (other, other_grown) = (selection((4, 1), (4, 1), "v"), selection((3, 8), (5, 1), "v"));
+        history.grow(1, other.clone(), other_grown.clone());
+        assert_eq!(history.shrink(1, &grown), None);
+        assert_eq!(history.shrink(1, &other_grown), Some(other));
+        assert_eq!(history.shrink(1, &other_grown), None);
+    }
+
+    #[test]
+    fn windows_have_their_own_history() {
+        let mut history = History::new();
+        let (first, grown) = (selection((1, 3), (1, 3), "v"), selection((1, 2), (1, 6), "v"));
+        history.grow(1, first.clone(), grown.clone());
+        assert_eq!(history.shrink(2, &grown), None);
+        // A selection in another mode is another selection.
+        assert_eq!(history.shrink(1, &selection((1, 2), (1, 6), "V")), None);
+        assert_eq!(history.shrink(1, &grown), Some(first));
+    }
+
+    #[test]
+    fn exclusive_marks_end_after_the_last_char() {
+        // a(bé)c
+        // 1234567
+        let fixture = Fixture::plain(&["a(bé)c"]);
+        let scanner = fixture.scanner();
+        let marks = |start: u64, end: u64, mode: &str, exclusive: bool| {
+            Marks { range: (Pos::new(1, start), Pos::new(1, end)), mode: mode.to_string(), exclusive }
+        };
+        let inside = selection((1, 3), (1, 4), "v");
+        assert_eq!(marks(3, 6, "v", true).selection(&scanner), inside);
+        assert_eq!(marks(3, 4, "v", false).selection(&scanner), inside);
+        assert_eq!(Marks::of(&scanner, &inside, true), (Pos::new(1, 3), Pos::new(1, 6)));
+        // Linewise marks go past the end of the line whatever 'selection' is.
+        let line = selection((1, 1), (1, 7), "V");
+        assert_eq!(marks(1, 2147483647, "V", true).selection(&scanner), line);
+        assert_eq!(Marks::of(&scanner, &line, true), (Pos::new(1, 1), Pos::new(1, 7)));
+    }
+
+}
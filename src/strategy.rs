@@ -0,0 +1,111 @@
+use neovim_lib::Value;
+
+use crate::scanner::{Pair, Scanner};
+use crate::utils::Pos;
+
+/// How the pairs around the cursor are ordered before a count picks one of them. The first one
+/// is what a plain `dih` acts on.
+///
+/// Innermost: the innermost pair first, then outwards.
+/// Prefer: the pairs opening with one of these delimiters first, in this order of preference and
+/// innermost first, then the rest. `['prefer', '{']` makes `dih` in `{ foo(|) }` empty the braces.
+/// Nearest: the pair with a delimiter closest to the cursor first, counting chars on either side.
+/// SameLine: the pairs opening and closing on the cursor line first.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Strategy {
+    #[default]
+    Innermost,
+    Prefer(Vec<String>),
+    Nearest,
+    SameLine,
+}
+
+impl Strategy {
+
+    /// A strategy as set in `g:delinhere_strategy`: its name, or a list of its name and its
+    /// arguments for `prefer`.
+    pub fn new(value: &Value) -> Strategy {
+        let (name, args) = match value {
+            Value::Array(list) => (list.first().and_then(Value::as_str), &list[list.len().min(1)..]),
+            _ => (value.as_str(), &[][..]),
+        };
+        match name {
+            Some("prefer") => Strategy::Prefer(args.iter().filter_map(Value::as_str).map(String::from).collect()),
+            Some("nearest") => Strategy::Nearest,
+            Some("same_line") => Strategy::SameLine,
+            _ => Strategy::Innermost,
+        }
+    }
+
+    /// Order `pairs`, given innermost first. The sort is stable, so pairs the strategy doesn't
+    /// tell apart stay innermost first.
+    pub fn order(&self, mut pairs: Vec<Pair>, scanner: &Scanner, cursor: Pos) -> Vec<Pair> {
+        match self {
+            Strategy::Innermost => {},
+            Strategy::Prefer(opens) => pairs.sort_by_key(|pair| {
+                let open = pair.bpair.to_simple_string_open();
+                opens.iter().position(|o| *o == open).unwrap_or(opens.len())
+            }),
+            Strategy::Nearest => pairs.sort_by_key(|pair| {
+                let before = scanner.distance(pair.open_end, cursor);
+                let after = scanner.distance(cursor, pair.close);
+                before.min(after)
+            }),
+            Strategy::SameLine => pairs.sort_by_key(|pair| {
+                !(pair.open.line() == cursor.line() && pair.close.line() == cursor.line())
+            }),
+        }
+        pairs
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::tests::Fixture;
+
+    #[test]
+    fn new_reads_a_name_or_a_list() {
+        assert_eq!(Strategy::new(&Value::from("nearest")), Strategy::Nearest);
+        assert_eq!(Strategy::new(&Value::from("same_line")), Strategy::SameLine);
+        let prefer = Value::from(vec![Value::from("prefer"), Value::from("{"), Value::from("[")]);
+        assert_eq!(Strategy::new(&prefer), Strategy::Prefer(vec![String::from("{"), String::from("[")]));
+        assert_eq!(Strategy::new(&Value::from("widest")), Strategy::Innermost);
+        assert_eq!(Strategy::new(&Value::Array(Vec::new())), Strategy::Innermost);
+    }
+
+    /// The columns the pairs around `cursor` open at, in the order of `strategy`.
+    fn ordered(fixture: &Fixture, strategy: Strategy, cursor: Pos) -> Vec<u64> {
+        let scanner = fixture.scanner();
+        let stack = scanner.stack(cursor, cursor);
+        strategy.order(stack, &scanner, cursor).iter().map(|pair| pair.open.col()).collect()
+    }
+
+    #[test]
+    fn prefer_puts_its_delimiters_first_in_order() {
+        // { f(a, [b]) }
+        // 1234567890123
+        let fixture = Fixture::plain(&["{ f(a, [b]) }"]);
+        let cursor = Pos::new(1, 9);
+        assert_eq!(ordered(&fixture, Strategy::Innermost, cursor), vec![8, 4, 1]);
+        assert_eq!(ordered(&fixture, Strategy::Prefer(vec![String::from("{")]), cursor), vec![1, 8, 4]);
+        let prefer = Strategy::Prefer(vec![String::from("("), String::from("{")]);
+        assert_eq!(ordered(&fixture, prefer, cursor), vec![4, 1, 8]);
+    }
+
+    #[test]
+    fn nearest_and_same_line_reorder_what_they_tell_apart() {
+        let fixture = Fixture::plain(&["f(", "  g(a, b),", ")"]);
+        let scanner = fixture.scanner();
+        let cursor = Pos::new(2, 5);
+        let outwards = scanner.stack(cursor, cursor);
+        let inwards: Vec<Pair> = outwards.iter().rev().cloned().collect();
+        let lines = |pairs: Vec<Pair>| pairs.iter().map(|pair| pair.open.line()).collect::<Vec<u64>>();
+        assert_eq!(lines(outwards.clone()), vec![2, 1]);
+        assert_eq!(lines(Strategy::Nearest.order(inwards.clone(), &scanner, cursor)), vec![2, 1]);
+        assert_eq!(lines(Strategy::SameLine.order(inwards.clone(), &scanner, cursor)), vec![2, 1]);
+        assert_eq!(lines(Strategy::Innermost.order(inwards, &scanner, cursor)), vec![1, 2]);
+    }
+
+}
@@ -0,0 +1,249 @@
+/// Which kind of markup tag was found.
+///
+/// Open: `<div class="x">`
+/// Close: `</div>`
+/// Lone: `<br>`, `<img src="a.png">`, `<Foo />`. Void elements and self-closing tags have no
+/// body, so they are never a pair.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagKind {
+    Open,
+    Close,
+    Lone,
+}
+
+/// A markup tag found on a line, from its `<` at byte offset start to its `>` at byte offset end.
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub name: String,
+    pub kind: TagKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Tag {
+    /// Whether byte offset b is part of the tag, its angle brackets included.
+    pub fn contains(&self, b: usize) -> bool {
+        self.start <= b && b <= self.end
+    }
+}
+
+/// HTML elements that can't have a body, so `<br>` needs no `</br>`.
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// A tag whose attributes go on to the next line, like it's usual in JSX:
+///
+///     <div
+///       className="x">
+///
+/// name: its name.
+/// closing: it is a `</name` tag.
+/// col: the 1-based byte column of its `<`.
+/// lines: how many lines above the current one it started.
+/// quote, braces: where the walk through its attributes is, see `walk`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pending {
+    pub name: String,
+    pub closing: bool,
+    pub col: u64,
+    pub lines: u64,
+    quote: Option<char>,
+    braces: usize,
+}
+
+/// How a pending tag goes on on a line.
+///
+/// Closed: it ends on the line, at the `>` at this byte offset.
+/// Continues: it goes on to the next line too.
+/// Dropped: it was no tag after all, another one starts before it ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resumed {
+    Closed(TagKind, usize),
+    Continues(Pending),
+    Dropped,
+}
+
+/// Finds the markup tags of a single line, left to right, from byte offset `from` on. A tag that
+/// doesn't end on the line is returned as pending, see `resume`.
+///
+/// The `<` of a start tag can't be stuck to an identifier on its left, so the generics in
+/// `useState<string>()` are not taken for tags. Attribute values may contain `>`, both quoted
+/// (`title="a > b"`) and as JSX expressions (`onClick={() => go()}`).
+pub fn tags(line: &str, from: usize) -> (Vec<Tag>, Option<Pending>) {
+
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let mut found = Vec::new();
+    let mut i = chars.iter().position(|(b, _)| *b >= from).unwrap_or(chars.len());
+
+    while i < chars.len() {
+        if chars[i].1 != '<' {
+            i += 1;
+            continue
+        }
+        match parse_tag(&chars, i) {
+            Some(Ok((tag, j))) => {
+                found.push(tag);
+                i = j + 1;
+            },
+            Some(Err(pending)) => return (found, Some(pending)),
+            None => i += 1,
+        }
+    }
+
+    (found, None)
+
+}
+
+/// Walk `line` as the rest of the attributes of `pending`.
+pub fn resume(line: &str, pending: &Pending) -> Resumed {
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let (mut quote, mut braces) = (pending.quote, pending.braces);
+    match walk(&chars, 0, &mut quote, &mut braces) {
+        Walk::Close(j) => Resumed::Closed(kind(&chars, j, &pending.name, pending.closing), chars[j].0),
+        Walk::Abort => Resumed::Dropped,
+        Walk::End => Resumed::Continues(Pending { lines: pending.lines + 1, quote, braces, ..pending.clone() }),
+    }
+}
+
+fn is_name_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '-' || ch == ':' || ch == '.'
+}
+
+/// Where a walk through attributes stopped: at the `>` ending the tag, on a `<` that means it was
+/// no tag, or at the end of the line.
+enum Walk {
+    Close(usize),
+    Abort,
+    End,
+}
+
+/// Walk attributes from chars[j] up to the `>` ending their tag. `quote` is the quote of the
+/// value the walk is in, if any, and `braces` how many JSX expressions it is in.
+fn walk(chars: &[(usize, char)], mut j: usize, quote: &mut Option<char>, braces: &mut usize) -> Walk {
+    while j < chars.len() {
+        let ch = chars[j].1;
+        match *quote {
+            Some(q) => if ch == q { *quote = None },
+            None => match ch {
+                '"' | '\'' => *quote = Some(ch),
+                '{' => *braces += 1,
+                '}' => *braces = braces.saturating_sub(1),
+                '<' if *braces == 0 => return Walk::Abort,
+                '>' if *braces == 0 => return Walk::Close(j),
+                _ => {},
+            }
+        }
+        j += 1;
+    }
+    Walk::End
+}
+
+/// The kind of the tag named `name` whose `>` is at chars[j].
+fn kind(chars: &[(usize, char)], j: usize, name: &str, closing: bool) -> TagKind {
+    if closing {
+        TagKind::Close
+    }
+    else if j > 0 && chars[j - 1].1 == '/' || VOID_ELEMENTS.contains(&&name.to_lowercase()[..]) {
+        TagKind::Lone
+    }
+    else {
+        TagKind::Open
+    }
+}
+
+/// Parse the tag whose `<` is at chars[i]. Returns it with the index of its `>`, or what is
+/// pending when it doesn't end on the line.
+fn parse_tag(chars: &[(usize, char)], i: usize) -> Option<Result<(Tag, usize), Pending>> {
+
+    let at = |k: usize| chars.get(k).map(|(_, ch)| *ch);
+    let closing = at(i + 1) == Some('/');
+    let name_start = if closing { i + 2 } else { i + 1 };
+
+    if !at(name_start).is_some_and(char::is_alphabetic) {
+        return None
+    }
+    if !closing && i > 0 && (chars[i - 1].1.is_alphanumeric() || chars[i - 1].1 == '_') {
+        return None
+    }
+
+    let mut j = name_start;
+    while at(j).is_some_and(is_name_char) {
+        j += 1;
+    }
+    let name: String = chars[name_start..j].iter().map(|(_, ch)| *ch).collect();
+
+    let (mut quote, mut braces) = (None, 0);
+    match walk(chars, j, &mut quote, &mut braces) {
+        Walk::Close(j) => {
+            let kind = kind(chars, j, &name, closing);
+            Some(Ok((Tag { name, kind, start: chars[i].0, end: chars[j].0 }, j)))
+        },
+        Walk::Abort => None,
+        // The attributes go on to the next line, but only a blank can come after the name.
+        Walk::End if at(j).is_none_or(char::is_whitespace) => {
+            Some(Err(Pending { name, closing, col: chars[i].0 as u64 + 1, lines: 1, quote, braces }))
+        },
+        Walk::End => None,
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The (name, kind, start, end) of the tags of a line.
+    fn found(line: &str) -> Vec<(String, TagKind, usize, usize)> {
+        tags(line, 0).0.into_iter().map(|t| (t.name, t.kind, t.start, t.end)).collect()
+    }
+
+    fn tag(name: &str, kind: TagKind, start: usize, end: usize) -> (String, TagKind, usize, usize) {
+        (name.to_string(), kind, start, end)
+    }
+
+    #[test]
+    fn open_and_close_tags() {
+        assert_eq!(found(r#"<div class="x">a</div>"#), vec![
+            tag("div", TagKind::Open, 0, 14),
+            tag("div", TagKind::Close, 16, 21),
+        ]);
+    }
+
+    #[test]
+    fn void_and_self_closing_tags_are_lone() {
+        assert_eq!(found(r#"<br><img src="a.png"><Foo />"#), vec![
+            tag("br", TagKind::Lone, 0, 3),
+            tag("img", TagKind::Lone, 4, 20),
+            tag("Foo", TagKind::Lone, 21, 27),
+        ]);
+    }
+
+    #[test]
+    fn generics_are_no_tags() {
+        assert_eq!(found("const [a, b] = useState<string>();"), vec![]);
+    }
+
+    #[test]
+    fn attributes_may_hold_a_close() {
+        assert_eq!(found(r#"<a title="a > b">"#), vec![tag("a", TagKind::Open, 0, 16)]);
+        assert_eq!(found("<button onClick={() => go()}>"), vec![tag("button", TagKind::Open, 0, 28)]);
+    }
+
+    #[test]
+    fn attributes_may_go_on_to_the_next_lines() {
+        let (found, pending) = tags("  <a><div", 0);
+        assert_eq!(found.len(), 1);
+        let first = pending.unwrap();
+        assert_eq!((&first.name[..], first.col, first.lines), ("div", 6, 1));
+        assert_eq!(resume("  <p>", &first), Resumed::Dropped);
+        let pending = match resume(r#"  title="a >"#, &first) {
+            Resumed::Continues(pending) => pending,
+            resumed => panic!("{:?}", resumed),
+        };
+        assert_eq!(pending.lines, 2);
+        assert_eq!(resume(r#"  b" onClick={() => go()}>x"#, &pending), Resumed::Closed(TagKind::Open, 25));
+    }
+
+}
@@ -0,0 +1,145 @@
+use neovim_lib::Value;
+
+use crate::scanner::Pair;
+use crate::utils::{BPairs, Pos};
+
+/// Lua run by the lookup. Walks up the syntax tree of the buffer from the cursor, keeping every
+/// node whose first and last children are matching bracket or quote tokens with the cursor on or
+/// between them, innermost first, like the text backend does. Each one comes as
+///
+///     {open token, close token, {open range}, {close range}, {{child range}, ...}}
+///
+/// with the ranges of its named children that are not comments, its arguments. Ranges are
+/// tree-sitter ones: {start row, start col, end row, end col}, 0-based and end exclusive.
+/// Returns nil when the filetype doesn't use the tree-sitter backend (see `g:delinhere_backends`)
+/// or when it has no parser.
+pub const QUERY: &str = r#"
+local backends = vim.g.delinhere_backends or {}
+if (backends[vim.bo.filetype] or backends['*']) ~= 'treesitter' then
+  return nil
+end
+local ok, found = pcall(function()
+  local parser = vim.treesitter.get_parser(0)
+  local root = parser:parse()[1]:root()
+  local cursor = vim.api.nvim_win_get_cursor(0)
+  local row, col = cursor[1] - 1, cursor[2]
+  local closers = {['(']=')', ['[']=']', ['{']='}', ['<']='>', ['"']='"', ["'"]="'", ['`']='`'}
+  local function before(r1, c1, r2, c2) return r1 < r2 or (r1 == r2 and c1 < c2) end
+  local found = {}
+  local node = root:descendant_for_range(row, col, row, col)
+  while node do
+    local count = node:child_count()
+    if count >= 2 then
+      local first, last = node:child(0), node:child(count - 1)
+      local fr, fc = first:range()
+      local _, _, lr, lc = last:range()
+      if not first:named() and not last:named() and closers[first:type()] == last:type()
+          and not before(row, col, fr, fc) and before(row, col, lr, lc) then
+        local args = {}
+        for i = 1, count - 2 do
+          local child = node:child(i)
+          if child:named() and child:type() ~= 'comment' then
+            table.insert(args, {child:range()})
+          end
+        end
+        table.insert(found, {first:type(), last:type(), {first:range()}, {last:range()}, args})
+      end
+    end
+    node = node:parent()
+  end
+  return found
+end)
+if ok then return found end
+return nil
+"#;
+
+/// A node `QUERY` found: its delimiters as a pair, and its arguments as (first byte, end)
+/// positions, the end being one byte past the last one.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub pair: Pair,
+    pub args: Vec<(Pos, Pos)>,
+}
+
+impl Node {
+
+    /// Decode what `QUERY` returned, innermost first. Empty for nil.
+    pub fn stack(value: &Value) -> Vec<Node> {
+        value.as_array().map_or(Vec::new(), |nodes| nodes.iter().filter_map(Self::new).collect())
+    }
+
+    fn new(value: &Value) -> Option<Node> {
+        let found = value.as_array()?;
+        let open = found.first()?.as_str()?;
+        let close = found.get(1)?.as_str()?;
+        let (open_start, open_end) = Self::range(found.get(2)?)?;
+        let (close_start, close_end) = Self::range(found.get(3)?)?;
+        let args = found.get(4)?.as_array()?.iter().filter_map(Self::range).collect();
+
+        let bpair = match open {
+            "<" => BPairs::Angle,
+            _ => open.chars().next()
+                .and_then(|ch| BPairs::from_open_char(ch).or_else(|| BPairs::from_quote_char(ch)))
+                .unwrap_or_else(|| BPairs::Custom(open.to_string(), close.to_string())),
+        };
+        let pair = Pair {
+            bpair,
+            open: open_start,
+            open_end: Pos::new(open_end.line(), open_end.col() - 1),
+            close: close_start,
+            close_len: close_end.col() - close_start.col(),
+        };
+
+        Some(Node { pair, args })
+    }
+
+    /// A tree-sitter range as `Pos` (1-based line and byte column) of its start and end.
+    fn range(value: &Value) -> Option<(Pos, Pos)> {
+        let range = value.as_array()?;
+        let at = |i: usize| range.get(i).and_then(Value::as_u64);
+        Some((Pos::new(at(0)? + 1, at(1)? + 1), Pos::new(at(2)? + 1, at(3)? + 1)))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tree-sitter range, 0-based and end exclusive.
+    fn range(start: (u64, u64), end: (u64, u64)) -> Value {
+        Value::from(vec![Value::from(start.0), Value::from(start.1), Value::from(end.0), Value::from(end.1)])
+    }
+
+    /// What `QUERY` gives for a node delimited by `open` and `close`.
+    fn found(open: &str, close: &str, args: Vec<Value>) -> Value {
+        Value::from(vec![
+            Value::from(open), Value::from(close),
+            range((0, 1), (0, 2 + open.len() as u64 - 1)),
+            range((1, 2), (1, 3)),
+            Value::from(args),
+        ])
+    }
+
+    #[test]
+    fn new_reads_positions_as_pos() {
+        // f(a,
+        //   b)
+        let node = Node::new(&found("(", ")", vec![range((0, 2), (0, 3)), range((1, 2), (1, 3))])).unwrap();
+        assert_eq!(node.pair.bpair, BPairs::Paren);
+        assert_eq!((node.pair.open, node.pair.open_end), (Pos::new(1, 2), Pos::new(1, 2)));
+        assert_eq!((node.pair.close, node.pair.close_len), (Pos::new(2, 3), 1));
+        assert_eq!(node.args, vec![(Pos::new(1, 3), Pos::new(1, 4)), (Pos::new(2, 3), Pos::new(2, 4))]);
+    }
+
+    #[test]
+    fn new_tells_the_delimiters_apart() {
+        let bpair = |open: &str, close: &str| Node::new(&found(open, close, Vec::new())).unwrap().pair.bpair;
+        assert_eq!(bpair("\"", "\""), BPairs::DQuote);
+        assert_eq!(bpair("<", ">"), BPairs::Angle);
+        assert_eq!(bpair("«", "»"), BPairs::Custom("«".to_string(), "»".to_string()));
+        assert!(Node::new(&Value::from(vec![Value::from("(")])).is_none());
+        assert!(Node::stack(&Value::Nil).is_empty());
+    }
+
+}
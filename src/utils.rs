@@ -22,10 +22,6 @@ impl Pos {
         self.col
     }
 
-    pub fn get(&self) -> (u64, u64) {
-        (self.line, self.col)
-    }
-
     fn to_char_index(&self) -> (u64, u64) {
         (self.line-1, self.col-1)
     }
@@ -54,28 +50,42 @@ impl<'a> PartialOrd<&'a Pos> for Pos {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum BPairs {
     Brack,
     Paren,
-    Curly
+    Curly,
+    Angle,
+    DQuote,
+    SQuote,
+    BQuote,
+    Tag(String),
+    Custom(String, String),
+    Keyword(Vec<String>, Vec<String>),
+    Env(String),
+    LeftRight,
+    Comment(String, String),
+    LineComment
 }
 
 impl BPairs{
 
-    pub fn to_string_pair(&self) -> (String, String) {
-        match &self {
-            BPairs::Brack   =>  (String::from(r"\["), String::from(r"\]")),
-            BPairs::Paren   =>  (String::from("("), String::from(")")),
-            BPairs::Curly   =>  (String::from("{"), String::from("}")),
-        }
-    }
-
     pub fn to_simple_string_open(&self) -> String {
         match &self {
             BPairs::Brack   =>  String::from("["),
             BPairs::Paren   =>  String::from("("),
-            BPairs::Curly   =>  String::from("{")
+            BPairs::Curly   =>  String::from("{"),
+            BPairs::Angle   =>  String::from("<"),
+            BPairs::DQuote  =>  String::from("\""),
+            BPairs::SQuote  =>  String::from("'"),
+            BPairs::BQuote  =>  String::from("`"),
+            BPairs::Tag(_)  =>  String::from("t"),
+            BPairs::Custom(open, _) => open.clone(),
+            BPairs::Keyword(openers, _) => openers[0].clone(),
+            BPairs::Env(name) => format!("\\begin{{{}}}", name),
+            BPairs::LeftRight => String::from("\\left"),
+            BPairs::Comment(start, _) => start.clone(),
+            BPairs::LineComment => String::new()
         }
     }
 
@@ -83,11 +93,53 @@ impl BPairs{
         match &self {
             BPairs::Brack   =>  String::from("]"),
             BPairs::Paren   =>  String::from(")"),
-            BPairs::Curly   =>  String::from("}")
+            BPairs::Curly   =>  String::from("}"),
+            BPairs::Angle   =>  String::from(">"),
+            BPairs::DQuote  =>  String::from("\""),
+            BPairs::SQuote  =>  String::from("'"),
+            BPairs::BQuote  =>  String::from("`"),
+            BPairs::Tag(_)  =>  String::from("t"),
+            BPairs::Custom(_, close) => close.clone(),
+            BPairs::Keyword(_, closers) => closers[0].clone(),
+            BPairs::Env(name) => format!("\\end{{{}}}", name),
+            BPairs::LeftRight => String::from("\\right"),
+            BPairs::Comment(_, end) => end.clone(),
+            BPairs::LineComment => String::new()
+        }
+    }
+
+    pub fn from_open_char(ch: char) -> Option<BPairs> {
+        match ch {
+            '[' => Some(BPairs::Brack),
+            '(' => Some(BPairs::Paren),
+            '{' => Some(BPairs::Curly),
+            _   => None
         }
     }
 
+    pub fn from_close_char(ch: char) -> Option<BPairs> {
+        match ch {
+            ']' => Some(BPairs::Brack),
+            ')' => Some(BPairs::Paren),
+            '}' => Some(BPairs::Curly),
+            _   => None
+        }
+    }
+
+    /// Quotes open and close with the same char, which side they are depends on the rest of the
+    /// line, see `lexer::lex`.
+    pub fn from_quote_char(ch: char) -> Option<BPairs> {
+        match ch {
+            '"' => Some(BPairs::DQuote),
+            '\'' => Some(BPairs::SQuote),
+            '`' => Some(BPairs::BQuote),
+            _   => None
+        }
+    }
 
+    /// The pairs whose chars are always brackets. `Angle` and the quotes are left out because
+    /// whether a char is one of their ends depends on its context, see `angle::angle_brackets`
+    /// and `lexer::lex`.
     pub fn array() -> [BPairs;3] {
         [BPairs::Brack, BPairs::Paren, BPairs::Curly]
     }